@@ -1,3 +1,36 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() {
   napi_build::setup();
+
+  // Links libchafa (and glib, which it is built on) and finds their headers.
+  // 1.16 is the first release with CHAFA_SYMBOL_TAG_OCTANT.
+  let chafa = pkg_config::Config::new()
+    .atleast_version("1.16")
+    .probe("chafa")
+    .expect("libchafa is required: install the chafa development package");
+
+  let bindings = bindgen::Builder::default()
+    .header_contents("chafa_wrapper.h", "#include <chafa.h>\n")
+    .clang_args(
+      chafa
+        .include_paths
+        .iter()
+        .map(|path| format!("-I{}", path.display())),
+    )
+    .allowlist_function("chafa_.*")
+    .allowlist_function("g_string_free")
+    .allowlist_function("g_get_environ")
+    .allowlist_function("g_strfreev")
+    .allowlist_type("Chafa.*")
+    .default_enum_style(bindgen::EnumVariation::ModuleConsts)
+    .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
+    .generate()
+    .expect("Unable to generate chafa bindings");
+
+  let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+  bindings
+    .write_to_file(out_path.join("chafa_bindings.rs"))
+    .expect("Couldn't write chafa bindings");
 }
@@ -0,0 +1,370 @@
+// Safe wrappers around libchafa, mirroring ChafaInfo and detect_terminal in c_interop.
+// The raw bindings are generated by bindgen in build.rs.
+
+use std::ptr::NonNull;
+
+use napi::{Error, Result, Status};
+
+#[allow(non_upper_case_globals, non_camel_case_types, non_snake_case, dead_code, clippy::all)]
+mod ffi {
+    include!(concat!(env!("OUT_DIR"), "/chafa_bindings.rs"));
+}
+
+use ffi::{ChafaCanvasMode, ChafaPixelMode, ChafaPixelType, ChafaSymbolTags, ChafaTermSeq};
+
+const TRUE: ffi::gboolean = 1;
+const FALSE: ffi::gboolean = 0;
+
+/// chafa only returns NULL when it is out of memory, which JS gets to see as an error.
+fn non_null<T>(ptr: *mut T, function: &str) -> Result<NonNull<T>> {
+    NonNull::new(ptr).ok_or_else(|| Error::new(Status::GenericFailure, format!("{} returned NULL", function)))
+}
+
+/// What the terminal can do, guessed from the environment.
+pub struct TermInfo(NonNull<ffi::ChafaTermInfo>);
+
+impl TermInfo {
+    pub fn detect() -> Result<Self> {
+        let term_info = unsafe {
+            let envp = ffi::g_get_environ();
+            let term_info = ffi::chafa_term_db_detect(ffi::chafa_term_db_get_default(), envp);
+            ffi::g_strfreev(envp);
+            term_info
+        };
+        Ok(Self(non_null(term_info, "chafa_term_db_detect")?))
+    }
+
+    fn have_seq(&self, seq: ChafaTermSeq::Type) -> bool {
+        unsafe { ffi::chafa_term_info_have_seq(self.0.as_ptr(), seq) != FALSE }
+    }
+
+    fn have_seqs(&self, seqs: &[ChafaTermSeq::Type]) -> bool {
+        seqs.iter().all(|&seq| self.have_seq(seq))
+    }
+}
+
+impl Drop for TermInfo {
+    fn drop(&mut self) {
+        unsafe { ffi::chafa_term_info_unref(self.0.as_ptr()) }
+    }
+}
+
+/// The set of symbols chafa may draw with.
+pub struct SymbolMap(NonNull<ffi::ChafaSymbolMap>);
+
+impl SymbolMap {
+    pub fn new() -> Result<Self> {
+        Ok(Self(non_null(unsafe { ffi::chafa_symbol_map_new() }, "chafa_symbol_map_new")?))
+    }
+
+    pub fn add_by_tags(&mut self, tags: ChafaSymbolTags::Type) {
+        unsafe { ffi::chafa_symbol_map_add_by_tags(self.0.as_ptr(), tags) }
+    }
+}
+
+impl Drop for SymbolMap {
+    fn drop(&mut self) {
+        unsafe { ffi::chafa_symbol_map_unref(self.0.as_ptr()) }
+    }
+}
+
+/// The settings a canvas is created with.
+pub struct CanvasConfig(NonNull<ffi::ChafaCanvasConfig>);
+
+impl CanvasConfig {
+    pub fn new() -> Result<Self> {
+        Ok(Self(non_null(unsafe { ffi::chafa_canvas_config_new() }, "chafa_canvas_config_new")?))
+    }
+
+    pub fn set_canvas_mode(&mut self, mode: ChafaCanvasMode::Type) {
+        unsafe { ffi::chafa_canvas_config_set_canvas_mode(self.0.as_ptr(), mode) }
+    }
+
+    pub fn set_pixel_mode(&mut self, pixel_mode: ChafaPixelMode::Type) {
+        unsafe { ffi::chafa_canvas_config_set_pixel_mode(self.0.as_ptr(), pixel_mode) }
+    }
+
+    /// The canvas size, in cells.
+    pub fn set_geometry(&mut self, width: i32, height: i32) {
+        unsafe { ffi::chafa_canvas_config_set_geometry(self.0.as_ptr(), width, height) }
+    }
+
+    /// The size of a cell, in pixels.
+    pub fn set_cell_geometry(&mut self, width: i32, height: i32) {
+        unsafe { ffi::chafa_canvas_config_set_cell_geometry(self.0.as_ptr(), width, height) }
+    }
+
+    /// The config keeps its own copy of the map.
+    pub fn set_symbol_map(&mut self, symbol_map: &SymbolMap) {
+        unsafe { ffi::chafa_canvas_config_set_symbol_map(self.0.as_ptr(), symbol_map.0.as_ptr()) }
+    }
+
+    pub fn set_work_factor(&mut self, work_factor: f32) {
+        unsafe { ffi::chafa_canvas_config_set_work_factor(self.0.as_ptr(), work_factor) }
+    }
+}
+
+impl Drop for CanvasConfig {
+    fn drop(&mut self) {
+        unsafe { ffi::chafa_canvas_config_unref(self.0.as_ptr()) }
+    }
+}
+
+/// Turns pixels into printable terminal output.
+pub struct Canvas(NonNull<ffi::ChafaCanvas>);
+
+impl Canvas {
+    /// The canvas keeps its own copy of the config.
+    pub fn new(config: &CanvasConfig) -> Result<Self> {
+        Ok(Self(non_null(unsafe { ffi::chafa_canvas_new(config.0.as_ptr()) }, "chafa_canvas_new")?))
+    }
+
+    /// Fails if `pixels` is too small for the image, which chafa would read past.
+    pub fn draw_all_pixels(&mut self, pixel_type: ChafaPixelType::Type, pixels: &[u8], width: u32, height: u32, stride: u32) -> Result<()> {
+        if pixels.len() < stride as usize * height as usize || (stride as usize) < width as usize * 4 {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!("{} bytes is too small for a {}x{} image with stride {}", pixels.len(), width, height, stride),
+            ));
+        }
+        unsafe {
+            ffi::chafa_canvas_draw_all_pixels(
+                self.0.as_ptr(),
+                pixel_type,
+                pixels.as_ptr(),
+                width as i32,
+                height as i32,
+                stride as i32,
+            )
+        }
+        Ok(())
+    }
+
+    /// The escape sequences that draw the canvas on a terminal like `term_info`.
+    pub fn print(&self, term_info: &TermInfo) -> Vec<u8> {
+        unsafe {
+            let printable = ffi::chafa_canvas_print(self.0.as_ptr(), term_info.0.as_ptr());
+            let bytes = std::slice::from_raw_parts((*printable).str_ as *const u8, (*printable).len as usize).to_vec();
+            ffi::g_string_free(printable, TRUE);
+            bytes
+        }
+    }
+}
+
+impl Drop for Canvas {
+    fn drop(&mut self) {
+        unsafe { ffi::chafa_canvas_unref(self.0.as_ptr()) }
+    }
+}
+
+/// Fits an image into the given number of cells, keeping its aspect ratio.
+/// Returns the canvas size in cells.
+pub fn calc_canvas_geometry(image_width: u32, image_height: u32, width_cells: i32, height_cells: i32, font_ratio: f32) -> (i32, i32) {
+    let (mut width_cells, mut height_cells) = (width_cells, height_cells);
+    unsafe {
+        ffi::chafa_calc_canvas_geometry(
+            image_width as i32,
+            image_height as i32,
+            &mut width_cells,
+            &mut height_cells,
+            font_ratio,
+            TRUE,
+            FALSE,
+        );
+    }
+    (width_cells, height_cells)
+}
+
+fn get_default_pixel_mode(term_info: &TermInfo) -> ChafaPixelMode::Type {
+    if term_info.have_seq(ChafaTermSeq::CHAFA_TERM_SEQ_BEGIN_ITERM2_IMAGE) {
+        ChafaPixelMode::CHAFA_PIXEL_MODE_ITERM2
+    } else if term_info.have_seq(ChafaTermSeq::CHAFA_TERM_SEQ_BEGIN_KITTY_IMMEDIATE_IMAGE_V1) {
+        ChafaPixelMode::CHAFA_PIXEL_MODE_KITTY
+    } else if term_info.have_seq(ChafaTermSeq::CHAFA_TERM_SEQ_BEGIN_SIXELS) {
+        ChafaPixelMode::CHAFA_PIXEL_MODE_SIXELS
+    } else {
+        ChafaPixelMode::CHAFA_PIXEL_MODE_SYMBOLS
+    }
+}
+
+fn get_pixel_mode(term_info: &TermInfo) -> ChafaPixelMode::Type {
+    match std::env::var("TERM_EVERYTHING_PIXEL_MODE").as_deref() {
+        Ok("SYMBOLS") => ChafaPixelMode::CHAFA_PIXEL_MODE_SYMBOLS,
+        Ok("SIXELS") => ChafaPixelMode::CHAFA_PIXEL_MODE_SIXELS,
+        Ok("KITTY") => ChafaPixelMode::CHAFA_PIXEL_MODE_KITTY,
+        Ok("ITERM2") => ChafaPixelMode::CHAFA_PIXEL_MODE_ITERM2,
+        _ => get_default_pixel_mode(term_info),
+    }
+}
+
+fn get_default_canvas_mode(term_info: &TermInfo, pixel_mode: ChafaPixelMode::Type) -> ChafaCanvasMode::Type {
+    use ChafaTermSeq::*;
+    match pixel_mode {
+        ChafaPixelMode::CHAFA_PIXEL_MODE_ITERM2 | ChafaPixelMode::CHAFA_PIXEL_MODE_SIXELS | ChafaPixelMode::CHAFA_PIXEL_MODE_KITTY => {
+            ChafaCanvasMode::CHAFA_CANVAS_MODE_TRUECOLOR
+        }
+        _ => {
+            if term_info.have_seqs(&[
+                CHAFA_TERM_SEQ_SET_COLOR_FGBG_DIRECT,
+                CHAFA_TERM_SEQ_SET_COLOR_FG_DIRECT,
+                CHAFA_TERM_SEQ_SET_COLOR_BG_DIRECT,
+            ]) {
+                ChafaCanvasMode::CHAFA_CANVAS_MODE_TRUECOLOR
+            } else if term_info.have_seqs(&[
+                CHAFA_TERM_SEQ_SET_COLOR_FGBG_256,
+                CHAFA_TERM_SEQ_SET_COLOR_FG_256,
+                CHAFA_TERM_SEQ_SET_COLOR_BG_256,
+            ]) {
+                ChafaCanvasMode::CHAFA_CANVAS_MODE_INDEXED_240
+            } else if term_info.have_seqs(&[
+                CHAFA_TERM_SEQ_SET_COLOR_FGBG_16,
+                CHAFA_TERM_SEQ_SET_COLOR_FG_16,
+                CHAFA_TERM_SEQ_SET_COLOR_BG_16,
+            ]) {
+                ChafaCanvasMode::CHAFA_CANVAS_MODE_INDEXED_16
+            } else if term_info.have_seqs(&[CHAFA_TERM_SEQ_INVERT_COLORS, CHAFA_TERM_SEQ_RESET_ATTRIBUTES]) {
+                ChafaCanvasMode::CHAFA_CANVAS_MODE_FGBG_BGFG
+            } else {
+                ChafaCanvasMode::CHAFA_CANVAS_MODE_FGBG
+            }
+        }
+    }
+}
+
+fn get_canvas_mode(term_info: &TermInfo, pixel_mode: ChafaPixelMode::Type) -> ChafaCanvasMode::Type {
+    use ChafaCanvasMode::*;
+    match std::env::var("TERM_EVERYTHING_CANVAS_MODE").as_deref() {
+        Ok("TRUECOLOR") => CHAFA_CANVAS_MODE_TRUECOLOR,
+        Ok("INDEXED_256") => CHAFA_CANVAS_MODE_INDEXED_256,
+        Ok("INDEXED_240") => CHAFA_CANVAS_MODE_INDEXED_240,
+        Ok("INDEXED_16") => CHAFA_CANVAS_MODE_INDEXED_16,
+        Ok("FGBG_BGFG") => CHAFA_CANVAS_MODE_FGBG_BGFG,
+        Ok("FGBG") => CHAFA_CANVAS_MODE_FGBG,
+        Ok("INDEXED_8") => CHAFA_CANVAS_MODE_INDEXED_8,
+        Ok("INDEXED_16_8") => CHAFA_CANVAS_MODE_INDEXED_16_8,
+        _ => get_default_canvas_mode(term_info, pixel_mode),
+    }
+}
+
+/// $TERM_EVERYTHING_PIXEL_TYPE, if it names a pixel type.
+fn get_pixel_type_override() -> Option<ChafaPixelType::Type> {
+    use ChafaPixelType::*;
+    match std::env::var("TERM_EVERYTHING_PIXEL_TYPE").as_deref() {
+        Ok("RGBA8") => Some(CHAFA_PIXEL_RGBA8_UNASSOCIATED),
+        Ok("BGRA8") => Some(CHAFA_PIXEL_BGRA8_UNASSOCIATED),
+        Ok("ARGB8") => Some(CHAFA_PIXEL_ARGB8_UNASSOCIATED),
+        Ok("ABGR8") => Some(CHAFA_PIXEL_ABGR8_UNASSOCIATED),
+        Ok("RGBA8_PREMULTIPLIED") => Some(CHAFA_PIXEL_RGBA8_PREMULTIPLIED),
+        Ok("BGRA8_PREMULTIPLIED") => Some(CHAFA_PIXEL_BGRA8_PREMULTIPLIED),
+        Ok("ARGB8_PREMULTIPLIED") => Some(CHAFA_PIXEL_ARGB8_PREMULTIPLIED),
+        Ok("ABGR8_PREMULTIPLIED") => Some(CHAFA_PIXEL_ABGR8_PREMULTIPLIED),
+        _ => None,
+    }
+}
+
+fn get_symbol_tags() -> ChafaSymbolTags::Type {
+    use ChafaSymbolTags::*;
+    match std::env::var("TERM_EVERYTHING_SYMBOLS").as_deref() {
+        Ok("NONE") => CHAFA_SYMBOL_TAG_NONE,
+        Ok("SPACE") => CHAFA_SYMBOL_TAG_SPACE,
+        Ok("SOLID") => CHAFA_SYMBOL_TAG_SOLID,
+        Ok("STIPPLE") => CHAFA_SYMBOL_TAG_STIPPLE,
+        Ok("BLOCK") => CHAFA_SYMBOL_TAG_BLOCK,
+        Ok("BORDER") => CHAFA_SYMBOL_TAG_BORDER,
+        Ok("DIAGONAL") => CHAFA_SYMBOL_TAG_DIAGONAL,
+        Ok("DOT") => CHAFA_SYMBOL_TAG_DOT,
+        Ok("QUAD") => CHAFA_SYMBOL_TAG_QUAD,
+        Ok("HHALF") => CHAFA_SYMBOL_TAG_HHALF,
+        Ok("VHALF") => CHAFA_SYMBOL_TAG_VHALF,
+        Ok("HALF") => CHAFA_SYMBOL_TAG_HALF,
+        Ok("INVERTED") => CHAFA_SYMBOL_TAG_INVERTED,
+        Ok("BRAILLE") => CHAFA_SYMBOL_TAG_BRAILLE,
+        Ok("TECHNICAL") => CHAFA_SYMBOL_TAG_TECHNICAL,
+        Ok("GEOMETRIC") => CHAFA_SYMBOL_TAG_GEOMETRIC,
+        Ok("ASCII") => CHAFA_SYMBOL_TAG_ASCII,
+        Ok("ALPHA") => CHAFA_SYMBOL_TAG_ALPHA,
+        Ok("DIGIT") => CHAFA_SYMBOL_TAG_DIGIT,
+        Ok("ALNUM") => CHAFA_SYMBOL_TAG_ALNUM,
+        Ok("NARROW") => CHAFA_SYMBOL_TAG_NARROW,
+        Ok("WIDE") => CHAFA_SYMBOL_TAG_WIDE,
+        Ok("AMBIGUOUS") => CHAFA_SYMBOL_TAG_AMBIGUOUS,
+        Ok("UGLY") => CHAFA_SYMBOL_TAG_UGLY,
+        Ok("LEGACY") => CHAFA_SYMBOL_TAG_LEGACY,
+        Ok("SEXTANT") => CHAFA_SYMBOL_TAG_SEXTANT,
+        Ok("WEDGE") => CHAFA_SYMBOL_TAG_WEDGE,
+        Ok("LATIN") => CHAFA_SYMBOL_TAG_LATIN,
+        Ok("IMPORTED") => CHAFA_SYMBOL_TAG_IMPORTED,
+        Ok("OCTANT") => CHAFA_SYMBOL_TAG_OCTANT,
+        _ => CHAFA_SYMBOL_TAG_ALL,
+    }
+}
+
+/// Everything chafa needs to draw frames of one canvas size.
+/// This is the Rust equivalent of ChafaInfo in c_interop.
+pub struct ChafaInfo {
+    // Dropped in this order, like ~ChafaInfo.
+    canvas: Canvas,
+    _config: CanvasConfig,
+    _symbol_map: SymbolMap,
+    term_info: TermInfo,
+
+    pub width_cells: i32,
+    pub height_cells: i32,
+    pub width_of_a_cell_in_pixels: i32,
+    pub height_of_a_cell_in_pixels: i32,
+    pixel_type: ChafaPixelType::Type,
+}
+
+impl ChafaInfo {
+    pub fn new(
+        width_cells: i32,
+        height_cells: i32,
+        width_of_a_cell_in_pixels: i32,
+        height_of_a_cell_in_pixels: i32,
+        session_type_is_x11: bool,
+    ) -> Result<Self> {
+        let term_info = TermInfo::detect()?;
+        let pixel_mode = get_pixel_mode(&term_info);
+        let mode = get_canvas_mode(&term_info, pixel_mode);
+
+        let mut symbol_map = SymbolMap::new()?;
+        symbol_map.add_by_tags(get_symbol_tags());
+
+        let mut config = CanvasConfig::new()?;
+        config.set_canvas_mode(mode);
+        config.set_pixel_mode(pixel_mode);
+        config.set_geometry(width_cells, height_cells);
+        config.set_symbol_map(&symbol_map);
+        config.set_work_factor(0.0);
+        if width_of_a_cell_in_pixels > 0 && height_of_a_cell_in_pixels > 0 {
+            config.set_cell_geometry(width_of_a_cell_in_pixels, height_of_a_cell_in_pixels);
+        }
+        let canvas = Canvas::new(&config)?;
+
+        let pixel_type = get_pixel_type_override().unwrap_or(
+            if pixel_mode == ChafaPixelMode::CHAFA_PIXEL_MODE_KITTY && !session_type_is_x11 {
+                ChafaPixelType::CHAFA_PIXEL_RGBA8_UNASSOCIATED
+            } else {
+                ChafaPixelType::CHAFA_PIXEL_BGRA8_UNASSOCIATED
+            },
+        );
+
+        Ok(Self {
+            canvas,
+            _config: config,
+            _symbol_map: symbol_map,
+            term_info,
+            width_cells,
+            height_cells,
+            width_of_a_cell_in_pixels,
+            height_of_a_cell_in_pixels,
+            pixel_type,
+        })
+    }
+
+    /// Draws the image on the canvas and returns it as terminal output.
+    pub fn convert_image(&mut self, pixels: &[u8], width: u32, height: u32, stride: u32) -> Result<Vec<u8>> {
+        self.canvas.draw_all_pixels(self.pixel_type, pixels, width, height, stride)?;
+        Ok(self.canvas.print(&self.term_info))
+    }
+}
@@ -1,9 +1,9 @@
 #![deny(clippy::all)]
 
 use std::collections::HashMap;
-use std::ffi::{c_char, c_void, CString};
+use std::ffi::c_void;
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::PathBuf;
 use std::ptr;
 use std::sync::Mutex;
@@ -11,55 +11,10 @@ use std::sync::Mutex;
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 
-// --- Chafa FFI (Foreign Function Interface) ---
-// NOTE: These are handwritten FFI bindings for the `chafa` C library.
-// In a working build environment, these should be generated by `bindgen` in `build.rs`.
-// The signatures are based on the usage in the C++ code.
-#[repr(C)]
-pub struct GString {
-    pub str: *mut c_char,
-    pub len: libc::size_t,
-    pub allocated_len: libc::size_t,
-}
+mod chafa;
 
-#[repr(C)]
-pub struct ChafaSymbolMap;
-#[repr(C)]
-pub struct ChafaCanvas;
-
-#[allow(non_camel_case_types)]
-type gint = libc::c_int;
-#[allow(non_camel_case_types)]
-type guint = libc::c_uint;
-#[allow(non_camel_case_types)]
-type gdouble = libc::c_double;
-#[allow(non_camel_case_types)]
-type gboolean = libc::c_int;
-
-#[link(name = "chafa")]
-extern "C" {
-    fn chafa_calc_canvas_geometry(
-        image_width: gint,
-        image_height: gint,
-        canvas_width_pixels: *mut gint,
-        canvas_height_pixels: *mut gint,
-        font_ratio: gdouble,
-        center: gboolean,
-        scale: gboolean,
-    );
-    fn g_string_free(string: *mut GString, free_segment: gboolean);
-    // This is a simplification. The C++ code calls a method on a ChafaInfo object.
-    // I'm creating a plausible C-style function that would be in the bindings.
-    // A real implementation would need to match the actual Chafa API.
-    fn chafa_canvas_draw_all(
-        canvas: *mut ChafaCanvas,
-        pixels: *const u8,
-        width: gint,
-        height: gint,
-        rowstride: gint,
-    ) -> *mut GString;
-}
-// --- End Chafa FFI ---
+use chafa::ChafaInfo;
+use term_size::TermSize;
 
 // Represents the C++ SHM_Pool_Memory class
 struct ShmPoolMemory {
@@ -173,8 +128,14 @@ fn get_socket_path_from_name(socket_name: &str) -> Result<PathBuf> {
 pub fn listen_to_wayland_socket(socket_name: String) -> Result<i32> {
     let socket_path = get_socket_path_from_name(&socket_name)?;
 
-    // Remove the file if it exists
+    // Only remove a socket nobody is listening on anymore; a live one belongs to another compositor.
     if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(&socket_path).is_ok() {
+            return Err(Error::new(
+                Status::GenericFailure,
+                format!("{} is in use by another compositor", socket_name),
+            ));
+        }
         std::fs::remove_file(&socket_path).map_err(|e| {
             Error::new(
                 Status::GenericFailure,
@@ -191,8 +152,9 @@ pub fn listen_to_wayland_socket(socket_name: String) -> Result<i32> {
         )
     })?;
 
-    // Return the raw file descriptor
-    Ok(listener.as_raw_fd())
+    // Hand ownership of the descriptor to the caller, who is responsible for closing it.
+    // Returning as_raw_fd() would hand out a descriptor that is closed when the listener drops.
+    Ok(listener.into_raw_fd())
 }
 
 /// Translation of mmap_shm_pool_js
@@ -231,87 +193,101 @@ pub fn unmmap_shm_pool(client_state: External<ClientState>, shm_pool_id: u32) {
     pools.remove(&shm_pool_id);
 }
 
+/// Keeps the chafa state between frames.
+/// This is the Rust equivalent of Draw_State in c_interop.
+#[napi]
+pub struct DrawState {
+    session_type_is_x11: bool,
+    chafa_info: Option<ChafaInfo>,
+}
+
+#[napi]
+impl DrawState {
+    #[napi(constructor)]
+    pub fn new(session_type_is_x11: bool) -> Self {
+        DrawState {
+            session_type_is_x11,
+            chafa_info: None,
+        }
+    }
+
+    /// Recreates the chafa state only if the canvas or cell size changed.
+    fn resize_chafa_info_if_needed(&mut self, width_cells: i32, height_cells: i32, term_size: &TermSize) -> Result<&mut ChafaInfo> {
+        let chafa_info = match self.chafa_info.take() {
+            Some(info)
+                if info.width_cells == width_cells
+                    && info.height_cells == height_cells
+                    && info.width_of_a_cell_in_pixels == term_size.width_of_a_cell_in_pixels
+                    && info.height_of_a_cell_in_pixels == term_size.height_of_a_cell_in_pixels =>
+            {
+                info
+            }
+            _ => ChafaInfo::new(
+                width_cells,
+                height_cells,
+                term_size.width_of_a_cell_in_pixels,
+                term_size.height_of_a_cell_in_pixels,
+                self.session_type_is_x11,
+            )?,
+        };
+        Ok(self.chafa_info.insert(chafa_info))
+    }
+}
+
+/// Translation of init_draw_state_js
+#[napi]
+pub fn init_draw_state(session_type_is_x11: bool) -> DrawState {
+    DrawState::new(session_type_is_x11)
+}
+
 /// Translation of draw_desktop_js
 #[napi]
 pub fn draw_desktop(
-    // The Draw_State external object is not used in the C++ snippet, so I'm omitting it.
-    // It would be another External<T> if needed.
+    env: Env,
+    draw_state: &mut DrawState,
     desktop_buffer: Buffer,
     width: u32,
     height: u32,
     status_line: String,
 ) -> Result<Object> {
-    // Get terminal dimensions
-    let (term_width_cols, term_height_rows) =
-        term_size::dimensions().ok_or_else(|| Error::new(Status::GenericFailure, "Could not get terminal size"))?;
-
     let have_status_line = !status_line.is_empty();
     let status_line_height = if have_status_line { 1 } else { 0 };
 
-    let mut canvas_width = term_width_cols as i32;
-    // Assuming font ratio is 1.0 for simplicity. The C++ code gets this from somewhere.
-    let font_ratio = 1.0;
-    let mut canvas_height = (term_height_rows - status_line_height) as i32;
-
-    // Call the (unsafe) FFI function
-    unsafe {
-        chafa_calc_canvas_geometry(
-            width as i32,
-            height as i32,
-            &mut canvas_width,
-            &mut canvas_height,
-            font_ratio,
-            1, // TRUE
-            0, // FALSE
-        );
-    }
+    // Fit the desktop into the terminal, preserving its aspect ratio.
+    let term_size = TermSize::new();
+    let (width_cells, height_cells) = chafa::calc_canvas_geometry(
+        width,
+        height,
+        term_size.width_cells,
+        term_size.height_cells - status_line_height,
+        term_size.font_ratio,
+    );
 
-    // This part is highly speculative as it depends on the real Chafa API and bindings
-    let printable: *mut GString = unsafe {
-        // This is a placeholder for the real Chafa drawing logic.
-        // The C++ code has a `Draw_State` object that holds a `ChafaInfo` which does the conversion.
-        // A real implementation would need to create and manage a ChafaCanvas.
-        // For now, we assume a function exists that takes pixel data and returns a GString.
-        // Let's return an empty string to avoid a segfault if this code were to be run.
-        let empty_c_str = CString::new("").unwrap();
-        let g_string = GString {
-            str: empty_c_str.into_raw(),
-            len: 0,
-            allocated_len: 1,
-        };
-        Box::into_raw(Box::new(g_string))
-    };
+    let chafa_info = draw_state.resize_chafa_info_if_needed(width_cells, height_cells, &term_size)?;
+    let printable = chafa_info.convert_image(&desktop_buffer, width, height, width * 4)?;
 
-    // Build the output string
-    let mut output = String::new();
+    let mut output = Vec::with_capacity(printable.len() + status_line.len() + 8);
     if have_status_line {
         // ANSI escape codes from C++ code
         let move_cursor_to_home = "\x1B[H";
         let clear_line_after_cursor = "\x1B[K";
-        output.push_str(move_cursor_to_home);
-        output.push_str(&status_line);
-        output.push_str(clear_line_after_cursor);
-        output.push('\n');
-    }
-
-    if !printable.is_null() {
-        let printable_str = unsafe { std::ffi::CStr::from_ptr((*printable).str).to_string_lossy() };
-        output.push_str(&printable_str);
-        unsafe {
-            // Free the GString allocated by Chafa
-            g_string_free(printable, 1);
-        }
+        output.extend_from_slice(move_cursor_to_home.as_bytes());
+        output.extend_from_slice(status_line.as_bytes());
+        output.extend_from_slice(clear_line_after_cursor.as_bytes());
+        output.push(b'\n');
     }
+    output.extend_from_slice(&printable);
 
-    // Write to stdout
-    print!("{}", output);
     use std::io::Write;
-    std::io::stdout().flush().unwrap();
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&output)
+        .and_then(|()| stdout.flush())
+        .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to write to the terminal: {}", e)))?;
 
-    // Return canvas size object
     let mut result_obj = env.create_object()?;
-    result_obj.set("width_cells", canvas_width)?;
-    result_obj.set("height_cells", canvas_height)?;
+    result_obj.set("width_cells", width_cells)?;
+    result_obj.set("height_cells", height_cells)?;
 
     Ok(result_obj)
 }
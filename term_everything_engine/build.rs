@@ -0,0 +1,356 @@
+//! Generates the Rust protocol bindings from the Wayland XML files in
+//! `scripts/generate_protocol/protocols`. This is the Rust counterpart of
+//! `scripts/generate_protocol`, which generates the TypeScript bindings.
+//!
+//! For every interface it generates a module containing:
+//! - `NAME`, `VERSION` and the `INTERFACE` signature table used for decoding
+//! - a `Request` enum with one variant per request
+//! - a `send_<event>` function per event, which drops events the object's
+//!   version doesn't have
+//! - one module of constants per enum
+//!
+//! Every XML file in the directory is picked up, so adding a protocol only
+//! means dropping in its XML file.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROTOCOLS_DIR: &str = "../scripts/generate_protocol/protocols";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", PROTOCOLS_DIR);
+
+    let mut files: Vec<PathBuf> = fs::read_dir(PROTOCOLS_DIR)
+        .expect("failed to read the protocols directory")
+        .map(|entry| entry.expect("failed to read protocols directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "xml"))
+        .collect();
+    files.sort();
+
+    let mut out = String::from("// This file has been generated by build.rs, do not edit.\n\n");
+    for file in &files {
+        println!("cargo:rerun-if-changed={}", file.display());
+        build_protocol(file, &mut out);
+    }
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("protocols.rs");
+    fs::write(out_path, out).expect("failed to write generated protocols");
+}
+
+fn build_protocol(file: &Path, out: &mut String) {
+    let text = fs::read_to_string(file).expect("failed to read protocol file");
+    let document = roxmltree::Document::parse(&text)
+        .unwrap_or_else(|e| panic!("failed to parse {}: {}", file.display(), e));
+
+    let file_name = file.file_name().unwrap().to_string_lossy();
+    for interface in document.root_element().children().filter(|n| n.has_tag_name("interface")) {
+        gen_interface(&file_name, interface, out);
+    }
+}
+
+struct Arg {
+    name: String,
+    kind: String,
+    allow_null: bool,
+    /// Only `new_id` without an interface is "untyped"; it is sent on the
+    /// wire as (interface name, version, id).
+    interface: Option<String>,
+}
+
+struct Message {
+    name: String,
+    summary: Option<String>,
+    since: u32,
+    destructor: bool,
+    args: Vec<Arg>,
+}
+
+fn parse_messages(interface: roxmltree::Node, tag: &str) -> Vec<Message> {
+    interface
+        .children()
+        .filter(|n| n.has_tag_name(tag))
+        .map(|message| Message {
+            name: message.attribute("name").unwrap().to_string(),
+            summary: summary_of(message),
+            since: message.attribute("since").map_or(1, |s| s.parse().unwrap()),
+            destructor: message.attribute("type") == Some("destructor"),
+            args: message
+                .children()
+                .filter(|n| n.has_tag_name("arg"))
+                .map(|arg| Arg {
+                    name: arg.attribute("name").unwrap().to_string(),
+                    kind: arg.attribute("type").unwrap().to_string(),
+                    allow_null: arg.attribute("allow-null") == Some("true"),
+                    interface: arg.attribute("interface").map(str::to_string),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The trimmed summary of a node, if it has one that isn't blank.
+fn summary_of(node: roxmltree::Node) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name("description"))
+        .and_then(|d| d.attribute("summary"))
+        .or_else(|| node.attribute("summary"))
+        .and_then(non_empty_summary)
+}
+
+// An empty `///` line is clippy's empty_docs in the generated file.
+fn non_empty_summary(summary: &str) -> Option<String> {
+    let trimmed = summary.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+fn gen_interface(file_name: &str, interface: roxmltree::Node, out: &mut String) {
+    let name = interface.attribute("name").unwrap();
+    let version: u32 = interface.attribute("version").unwrap().parse().unwrap();
+    let requests = parse_messages(interface, "request");
+    let events = parse_messages(interface, "event");
+
+    writeln!(out, "/// `{}` from {}.", name, file_name).unwrap();
+    if let Some(summary) = summary_of(interface) {
+        writeln!(out, "///\n/// {}", summary).unwrap();
+    }
+    writeln!(out, "pub mod {} {{", name).unwrap();
+    writeln!(out, "    #[allow(unused_imports)]").unwrap();
+    writeln!(out, "    use std::os::unix::io::OwnedFd;").unwrap();
+    writeln!(out, "    #[allow(unused_imports)]").unwrap();
+    writeln!(out, "    use crate::message_decoder::DecodeError;").unwrap();
+    writeln!(out, "    #[allow(unused_imports)]").unwrap();
+    writeln!(out, "    use crate::wayland_client::WaylandClient;").unwrap();
+    writeln!(out, "    #[allow(unused_imports)]").unwrap();
+    writeln!(out, "    use crate::wayland_object::Event;").unwrap();
+    writeln!(out, "    use crate::wayland_object::ProtocolRequest;").unwrap();
+    writeln!(out, "    #[allow(unused_imports)]").unwrap();
+    writeln!(out, "    use crate::wayland_types::{{Argument, ArgumentType, Fixed, Interface, ObjectId}};\n").unwrap();
+
+    writeln!(out, "    pub const NAME: &str = \"{}\";", name).unwrap();
+    writeln!(out, "    pub const VERSION: u32 = {};\n", version).unwrap();
+
+    gen_interface_table(name, &requests, out);
+    gen_request_enum(&requests, out);
+    for (opcode, event) in events.iter().enumerate() {
+        gen_event_sender(opcode, event, out);
+    }
+    for en in interface.children().filter(|n| n.has_tag_name("enum")) {
+        gen_enum(en, out);
+    }
+
+    writeln!(out, "}}\n").unwrap();
+}
+
+/// The wire types of an argument, in order. Untyped new_ids expand to three.
+fn wire_types(arg: &Arg) -> Vec<&'static str> {
+    match arg.kind.as_str() {
+        "int" => vec!["Int"],
+        "uint" => vec!["Uint"],
+        "fixed" => vec!["Fixed"],
+        "string" => vec!["String"],
+        "array" => vec!["Array"],
+        "fd" => vec!["Fd"],
+        "object" => vec!["Object"],
+        "new_id" if arg.interface.is_none() => vec!["String", "Uint", "NewId"],
+        "new_id" => vec!["NewId"],
+        other => panic!("unknown argument type {}", other),
+    }
+}
+
+fn gen_interface_table(name: &str, requests: &[Message], out: &mut String) {
+    writeln!(out, "    pub static INTERFACE: Interface = Interface {{").unwrap();
+    writeln!(out, "        name: NAME,").unwrap();
+    writeln!(out, "        version: VERSION,").unwrap();
+    writeln!(out, "        requests: &[").unwrap();
+    for request in requests {
+        let types: Vec<String> = request
+            .args
+            .iter()
+            .flat_map(wire_types)
+            .map(|t| format!("ArgumentType::{}", t))
+            .collect();
+        writeln!(out, "            &[{}], // {}.{}", types.join(", "), name, request.name).unwrap();
+    }
+    writeln!(out, "        ],").unwrap();
+    let since: Vec<String> = requests.iter().map(|request| request.since.to_string()).collect();
+    writeln!(out, "        request_since: &[{}],", since.join(", ")).unwrap();
+    let destructors: Vec<String> = requests
+        .iter()
+        .enumerate()
+        .filter(|(_, request)| request.destructor)
+        .map(|(opcode, _)| opcode.to_string())
+        .collect();
+    writeln!(out, "        destructors: &[{}],", destructors.join(", ")).unwrap();
+    writeln!(out, "    }};\n").unwrap();
+}
+
+/// The fields a request argument decodes to, as (name, type, conversion).
+fn request_fields(arg: &Arg) -> Vec<(String, &'static str, &'static str)> {
+    let name = field_name(&arg.name);
+    match (arg.kind.as_str(), arg.allow_null) {
+        ("int", _) => vec![(name, "i32", "into_int")],
+        ("uint", _) => vec![(name, "u32", "into_uint")],
+        ("fixed", _) => vec![(name, "Fixed", "into_fixed")],
+        ("string", false) => vec![(name, "String", "into_string")],
+        ("string", true) => vec![(name, "Option<String>", "into_nullable_string")],
+        ("array", _) => vec![(name, "Vec<u8>", "into_array")],
+        ("fd", _) => vec![(name, "OwnedFd", "into_fd")],
+        ("object", false) => vec![(name, "ObjectId", "into_object")],
+        ("object", true) => vec![(name, "Option<ObjectId>", "into_nullable_object")],
+        ("new_id", _) if arg.interface.is_none() => vec![
+            (format!("{}_interface", arg.name), "String", "into_string"),
+            (format!("{}_version", arg.name), "u32", "into_uint"),
+            (name, "ObjectId", "into_new_id"),
+        ],
+        ("new_id", _) => vec![(name, "ObjectId", "into_new_id")],
+        (other, _) => panic!("unknown argument type {}", other),
+    }
+}
+
+fn gen_request_enum(requests: &[Message], out: &mut String) {
+    writeln!(out, "    #[derive(Debug)]").unwrap();
+    writeln!(out, "    pub enum Request {{").unwrap();
+    for request in requests {
+        if let Some(summary) = &request.summary {
+            writeln!(out, "        /// {}", summary).unwrap();
+        }
+        let fields: Vec<_> = request.args.iter().flat_map(request_fields).collect();
+        if fields.is_empty() {
+            writeln!(out, "        {},", camel_case(&request.name)).unwrap();
+        } else {
+            writeln!(out, "        {} {{", camel_case(&request.name)).unwrap();
+            for (field, ty, _) in &fields {
+                writeln!(out, "            {}: {},", field, ty).unwrap();
+            }
+            writeln!(out, "        }},").unwrap();
+        }
+    }
+    writeln!(out, "    }}\n").unwrap();
+
+    writeln!(out, "    impl ProtocolRequest for Request {{").unwrap();
+    writeln!(out, "        const INTERFACE: &'static Interface = &INTERFACE;\n").unwrap();
+    writeln!(
+        out,
+        "        fn parse(opcode: u16, args: Vec<Argument>) -> Result<Self, DecodeError> {{"
+    )
+    .unwrap();
+    if requests.is_empty() {
+        // The enum is uninhabited, so every opcode is unknown.
+        writeln!(out, "            let _ = args;").unwrap();
+        writeln!(out, "            Err(DecodeError::UnknownOpcode(opcode))").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}\n").unwrap();
+        return;
+    }
+    writeln!(out, "            #[allow(unused_mut, unused_variables)]").unwrap();
+    writeln!(out, "            let mut args = args.into_iter();").unwrap();
+    writeln!(out, "            Ok(match opcode {{").unwrap();
+    for (opcode, request) in requests.iter().enumerate() {
+        let fields: Vec<_> = request.args.iter().flat_map(request_fields).collect();
+        if fields.is_empty() {
+            writeln!(out, "                {} => Request::{},", opcode, camel_case(&request.name)).unwrap();
+        } else {
+            writeln!(out, "                {} => Request::{} {{", opcode, camel_case(&request.name)).unwrap();
+            for (field, _, conversion) in &fields {
+                writeln!(
+                    out,
+                    "                    {}: args.next().ok_or(DecodeError::Truncated)?.{}()?,",
+                    field, conversion
+                )
+                .unwrap();
+            }
+            writeln!(out, "                }},").unwrap();
+        }
+    }
+    writeln!(out, "                _ => return Err(DecodeError::UnknownOpcode(opcode)),").unwrap();
+    writeln!(out, "            }})").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+}
+
+fn gen_event_sender(opcode: usize, event: &Message, out: &mut String) {
+    let mut params = vec!["client: &mut WaylandClient".to_string(), "self_id: ObjectId".to_string()];
+    let mut values = Vec::new();
+    for arg in &event.args {
+        let name = field_name(&arg.name);
+        let (ty, value) = match (arg.kind.as_str(), arg.allow_null) {
+            ("int", _) => ("i32", format!("Argument::Int({})", name)),
+            ("uint", _) => ("u32", format!("Argument::Uint({})", name)),
+            ("fixed", _) => ("Fixed", format!("Argument::Fixed({})", name)),
+            ("string", false) => ("&str", format!("Argument::String(Some({}.to_string()))", name)),
+            ("string", true) => ("Option<&str>", format!("Argument::String({}.map(str::to_string))", name)),
+            ("array", _) => ("Vec<u8>", format!("Argument::Array({})", name)),
+            ("fd", _) => ("OwnedFd", format!("Argument::Fd({})", name)),
+            ("object", false) => ("ObjectId", format!("Argument::Object(Some({}))", name)),
+            ("object", true) => ("Option<ObjectId>", format!("Argument::Object({})", name)),
+            ("new_id", _) => ("ObjectId", format!("Argument::NewId({})", name)),
+            (other, _) => panic!("unknown argument type {}", other),
+        };
+        params.push(format!("{}: {}", name, ty));
+        values.push(value);
+    }
+
+    if let Some(summary) = &event.summary {
+        writeln!(out, "    /// {}", summary).unwrap();
+    }
+    if event.since > 1 {
+        writeln!(out, "    ///\n    /// Since version {}; objects of older versions don't get it.", event.since).unwrap();
+    }
+    writeln!(out, "    pub fn send_{}({}) {{", event.name, params.join(", ")).unwrap();
+    if event.since > 1 {
+        writeln!(out, "        if client.get_object_version(self_id) < {} {{", event.since).unwrap();
+        writeln!(out, "            return;").unwrap();
+        writeln!(out, "        }}").unwrap();
+    }
+    writeln!(
+        out,
+        "        client.send_event(Event::new(self_id, {}, vec![{}]));",
+        opcode,
+        values.join(", ")
+    )
+    .unwrap();
+    writeln!(out, "    }}\n").unwrap();
+}
+
+fn gen_enum(en: roxmltree::Node, out: &mut String) {
+    let name = en.attribute("name").unwrap();
+    if let Some(summary) = summary_of(en) {
+        writeln!(out, "    /// {}", summary).unwrap();
+    }
+    writeln!(out, "    pub mod {} {{", name).unwrap();
+    for entry in en.children().filter(|n| n.has_tag_name("entry")) {
+        let entry_name = entry.attribute("name").unwrap().to_uppercase();
+        // Entries like wl_output.transform "90" are not valid identifiers.
+        let entry_name = if entry_name.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("_{}", entry_name)
+        } else {
+            entry_name
+        };
+        if let Some(summary) = entry.attribute("summary").and_then(non_empty_summary) {
+            writeln!(out, "        /// {}", summary).unwrap();
+        }
+        writeln!(out, "        pub const {}: u32 = {};", entry_name, entry.attribute("value").unwrap()).unwrap();
+    }
+    writeln!(out, "    }}\n").unwrap();
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn field_name(name: &str) -> String {
+    match name {
+        "type" | "move" | "ref" | "fn" | "impl" | "mod" | "use" | "match" => format!("r#{}", name),
+        _ => name.to_string(),
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Instant;
+
+use crate::blend::{blend_rect, BlendMode};
+use crate::protocols::wl_shm::{bytes_per_pixel, convert_row_to_rgba, convert_row_to_rgba_scalar, SUPPORTED_FORMATS};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const ITERATIONS: u32 = 50;
+
+/// Measures the compositor's blending and pixel conversion throughput on a
+/// 1080p frame, and checks that every fast path matches the scalar one.
+/// Returns false if one does not.
+pub fn run() -> bool {
+    let mut ok = true;
+    let texture = premultiplied_noise(WIDTH * HEIGHT);
+    let background = premultiplied_noise(WIDTH * HEIGHT);
+
+    println!("Blending {}x{}, {} frames each:", WIDTH, HEIGHT, ITERATIONS);
+    let mut reference = background.clone();
+    blend_frame(BlendMode::Scalar, &texture, &mut reference);
+    for mode in [BlendMode::Scalar, BlendMode::Simd, BlendMode::Threaded] {
+        let mut canvas = background.clone();
+        blend_frame(mode, &texture, &mut canvas);
+        let matches = canvas == reference;
+        ok &= matches;
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            blend_frame(mode, &texture, &mut canvas);
+        }
+        report(&format!("{:?}", mode), start, matches);
+    }
+
+    println!("Converting {}x{} to RGBA, {} frames each:", WIDTH, HEIGHT, ITERATIONS);
+    for &format in SUPPORTED_FORMATS {
+        let stride = WIDTH * bytes_per_pixel(format).unwrap() as usize;
+        // Enough bytes for any format, up to 4 bytes per pixel.
+        let src = premultiplied_noise(WIDTH * HEIGHT);
+        let convert_frame = |convert: fn(u32, &[u8], &mut [u8]), dst: &mut [u8]| {
+            for (src, dst) in src.chunks_exact(stride).zip(dst.chunks_exact_mut(WIDTH * 4)) {
+                convert(format, src, dst);
+            }
+        };
+
+        let mut reference = vec![0; WIDTH * HEIGHT * 4];
+        convert_frame(convert_row_to_rgba_scalar, &mut reference);
+        for (name, convert) in [
+            ("Scalar", convert_row_to_rgba_scalar as fn(u32, &[u8], &mut [u8])),
+            ("Simd", convert_row_to_rgba),
+        ] {
+            let mut dst = vec![0; WIDTH * HEIGHT * 4];
+            convert_frame(convert, &mut dst);
+            let matches = dst == reference;
+            ok &= matches;
+
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                convert_frame(convert, &mut dst);
+            }
+            report(&format!("0x{:08x} {}", format, name), start, matches);
+        }
+    }
+    ok
+}
+
+fn blend_frame(mode: BlendMode, texture: &[u8], canvas: &mut [u8]) {
+    blend_rect(mode, texture, WIDTH * 4, canvas, WIDTH * 4, WIDTH, HEIGHT);
+}
+
+fn report(name: &str, start: Instant, matches: bool) {
+    let seconds = start.elapsed().as_secs_f64();
+    let megapixels = (WIDTH * HEIGHT) as f64 * ITERATIONS as f64 / 1e6;
+    println!(
+        "  {:<20} {:>8.1} Mpixel/s {:>7.1} frames/s{}",
+        name,
+        megapixels / seconds,
+        ITERATIONS as f64 / seconds,
+        if matches { "" } else { "  MISMATCH with scalar" }
+    );
+}
+
+/// Deterministic RGBA pixels: a mix of opaque, transparent and translucent
+/// ones, with colors no brighter than their alpha.
+fn premultiplied_noise(pixels: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let mut out = Vec::with_capacity(pixels * 4);
+    for _ in 0..pixels {
+        let random = next();
+        let alpha = match random % 4 {
+            0 => 255,
+            1 => 0,
+            _ => (random >> 24) as u8,
+        };
+        let channel = |shift: u32| ((random >> shift & 0xff) * alpha as u32 / 255) as u8;
+        out.extend_from_slice(&[channel(2), channel(10), channel(18), alpha]);
+    }
+    out
+}
@@ -0,0 +1,175 @@
+use rayon::prelude::*;
+use wide::{u16x16, u8x16};
+
+/// How the compositor blends surfaces onto the canvas.
+/// All of them produce the same pixels; the scalar one is the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Scalar,
+    Simd,
+    /// SIMD, with the rows split into bands across threads.
+    Threaded,
+}
+
+impl BlendMode {
+    /// Threaded, unless $TERM_EVERYTHING_BLEND asks for "scalar" or "simd".
+    pub fn from_env() -> Self {
+        match std::env::var("TERM_EVERYTHING_BLEND").as_deref() {
+            Ok("scalar") => BlendMode::Scalar,
+            Ok("simd") => BlendMode::Simd,
+            Ok("threaded") | Err(_) => BlendMode::Threaded,
+            Ok(other) => {
+                eprintln!("Warning: unknown TERM_EVERYTHING_BLEND '{}', using threaded", other);
+                BlendMode::Threaded
+            }
+        }
+    }
+}
+
+/// Rows per band handed to a thread. Smaller bands cost more in scheduling
+/// than the blending they save.
+const BAND_ROWS: usize = 32;
+
+/// Images smaller than this, like cursors, are not worth waking other threads for.
+const MIN_THREADED_PIXELS: usize = 64 * 1024;
+
+/// Blends `rows` rows of `width` RGBA pixels from `src` over `dst`.
+/// Both strides are in bytes.
+pub fn blend_rect(
+    mode: BlendMode,
+    src: &[u8],
+    src_stride: usize,
+    dst: &mut [u8],
+    dst_stride: usize,
+    width: usize,
+    rows: usize,
+) {
+    let row_bytes = width * 4;
+    let blend_row = match mode {
+        BlendMode::Scalar => blend_row_scalar,
+        BlendMode::Simd | BlendMode::Threaded => blend_row_simd,
+    };
+    if mode != BlendMode::Threaded || width * rows < MIN_THREADED_PIXELS {
+        for row in 0..rows {
+            let src_start = row * src_stride;
+            let dst_start = row * dst_stride;
+            blend_row(&src[src_start..src_start + row_bytes], &mut dst[dst_start..dst_start + row_bytes]);
+        }
+        return;
+    }
+    dst.par_chunks_mut(dst_stride)
+        .take(rows)
+        .enumerate()
+        .with_min_len(BAND_ROWS)
+        .for_each(|(row, dst_row)| {
+            let src_start = row * src_stride;
+            blend_row(&src[src_start..src_start + row_bytes], &mut dst_row[..row_bytes]);
+        });
+}
+
+/// Source-over blending of premultiplied RGBA pixels, which is what wl_shm
+/// buffers with alpha contain: dst = src + dst * (1 - src alpha).
+pub fn blend_row_scalar(src: &[u8], dst: &mut [u8]) {
+    for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        match src[3] {
+            255 => dst.copy_from_slice(src),
+            0 if src[..3] == [0, 0, 0] => {}
+            alpha => {
+                let inverse = 255 - alpha as u32;
+                for channel in 0..4 {
+                    let blended = src[channel] as u32 + (dst[channel] as u32 * inverse + 127) / 255;
+                    // Clients can send invalid premultiplied colors brighter than their alpha.
+                    dst[channel] = blended.min(255) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Same as `blend_row_scalar`, four pixels at a time.
+pub fn blend_row_simd(src: &[u8], dst: &mut [u8]) {
+    let mut src_chunks = src.chunks_exact(16);
+    let mut dst_chunks = dst.chunks_exact_mut(16);
+    for (src, dst) in (&mut src_chunks).zip(&mut dst_chunks) {
+        let src: [u8; 16] = src.try_into().unwrap();
+        let alphas = [src[3], src[7], src[11], src[15]];
+        if alphas == [255; 4] {
+            dst.copy_from_slice(&src);
+            continue;
+        }
+        if src == [0; 16] {
+            continue;
+        }
+
+        let mut inverse = [0u16; 16];
+        for (lanes, alpha) in inverse.chunks_exact_mut(4).zip(alphas) {
+            lanes.fill(255 - alpha as u16);
+        }
+        let s = u16x16::from(u8x16::new(src));
+        let d = u16x16::from(u8x16::new(dst[..].try_into().unwrap()));
+        // (d * inverse + 127) / 255 is the same as rounding d * inverse / 255,
+        // which is exact as (t + (t >> 8)) >> 8 with t = d * inverse + 128.
+        let t = d * u16x16::new(inverse) + u16x16::splat(128);
+        let scaled = (t + (t >> 8_u32)) >> 8_u32;
+        let blended = (s + scaled).min(u16x16::splat(255));
+        for (dst, channel) in dst.iter_mut().zip(blended.to_array()) {
+            *dst = channel as u8;
+        }
+    }
+    blend_row_scalar(src_chunks.remainder(), dst_chunks.into_remainder());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pixels with every kind of alpha the fast paths care about, and colors
+    /// that are often brighter than their alpha.
+    fn pixels(count: usize, mut seed: u32) -> Vec<u8> {
+        let mut next = move || {
+            // xorshift32
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut bytes = Vec::with_capacity(count * 4);
+        for _ in 0..count {
+            let [r, g, b, a] = next().to_le_bytes();
+            bytes.extend_from_slice(&match next() % 4 {
+                0 => [0, 0, 0, 0],
+                1 => [r, g, b, 255],
+                _ => [r, g, b, a],
+            });
+        }
+        bytes
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        // Odd widths, and tails of every length under four pixels.
+        for width in (1..=9).chain([15, 16, 17, 33]) {
+            let src = pixels(width, 1 + width as u32);
+            let dst = pixels(width, 1000 + width as u32);
+            let mut expected = dst.clone();
+            blend_row_scalar(&src, &mut expected);
+            let mut actual = dst;
+            blend_row_simd(&src, &mut actual);
+            assert_eq!(actual, expected, "width {}", width);
+        }
+    }
+
+    #[test]
+    fn threaded_matches_scalar() {
+        // Big enough to be split across threads, with strides wider than the rows.
+        let (width, rows) = (257, 300);
+        let (src_stride, dst_stride) = (width * 4 + 12, width * 4 + 4);
+        let src = pixels(src_stride / 4 * rows, 7);
+        let dst = pixels(dst_stride / 4 * rows, 11);
+        let mut expected = dst.clone();
+        blend_rect(BlendMode::Scalar, &src, src_stride, &mut expected, dst_stride, width, rows);
+        let mut actual = dst;
+        blend_rect(BlendMode::Threaded, &src, src_stride, &mut actual, dst_stride, width, rows);
+        assert!(actual == expected);
+    }
+}
@@ -0,0 +1,8 @@
+use std::os::unix::io::OwnedFd;
+
+/// Hands out the file descriptors received on a connection, in the order
+/// they were received, to the requests that have `fd` arguments.
+/// This is the Rust equivalent of File_Descriptor_Claim.ts.
+pub trait FileDescriptorClaim {
+    fn claim_file_descriptor(&mut self) -> Option<OwnedFd>;
+}
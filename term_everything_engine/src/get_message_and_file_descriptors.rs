@@ -0,0 +1,61 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+
+/// The most file descriptors a client may send with a single message.
+/// Matches MAX_FDS_OUT in libwayland.
+const MAX_FILE_DESCRIPTORS: usize = 28;
+
+/// Reads bytes from a client socket together with any file descriptors
+/// sent as SCM_RIGHTS ancillary data.
+/// This is the Rust equivalent of Get_Message_and_File_Descriptors.cpp.
+///
+/// Received file descriptors are appended to `fds`. Returns the number of
+/// bytes read; 0 means the client closed the connection.
+pub fn get_message_and_file_descriptors(
+    client_socket: RawFd,
+    buf: &mut [u8],
+    fds: &mut Vec<OwnedFd>,
+) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    let cmsg_space =
+        unsafe { libc::CMSG_SPACE((mem::size_of::<libc::c_int>() * MAX_FILE_DESCRIPTORS) as u32) };
+    // u64 keeps the control buffer aligned for cmsghdr.
+    let mut cmsg_buf = vec![0u64; (cmsg_space as usize).div_ceil(mem::size_of::<u64>())];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+    msg.msg_controllen = cmsg_space as _;
+
+    let n = unsafe { libc::recvmsg(client_socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Iterate through the control messages
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) }.cast::<libc::c_int>();
+            let data_len = header.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+            for i in 0..data_len / mem::size_of::<libc::c_int>() {
+                let fd = unsafe { data.add(i).read_unaligned() };
+                fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        eprintln!("[get_message_and_file_descriptors] Ancillary data was truncated, file descriptors were lost");
+    }
+
+    Ok(n as usize)
+}
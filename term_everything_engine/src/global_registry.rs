@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::wayland_client::WaylandClient;
+use crate::wayland_types::{Interface, ObjectId};
+
+/// A global the compositor can advertise through wl_registry.
+///
+/// Protocols implement this next to their object type and are registered
+/// with `GlobalRegistry::register`; nothing else in the engine needs to know
+/// about them.
+pub trait Global: Send + Sync {
+    fn interface(&self) -> &'static Interface;
+
+    /// The highest version clients may bind.
+    fn version(&self) -> u32 {
+        self.interface().version
+    }
+
+    /// Optional extensions return false and are only advertised when
+    /// enabled with `--enable-globals`.
+    fn enabled_by_default(&self) -> bool {
+        true
+    }
+
+    /// Globals that come and go at runtime, like a device being plugged in,
+    /// return false while they are gone. Checked every frame by
+    /// `update_globals`, which announces changes with global and global_remove.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Creates the per-client object at the bound version and sends its
+    /// initial events.
+    fn bind(&self, client: &mut WaylandClient, id: ObjectId, version: u32);
+}
+
+/// Which globals to advertise, by interface name.
+/// Disabling wins over enabling.
+#[derive(Debug, Default)]
+pub struct GlobalFilter {
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+}
+
+impl GlobalFilter {
+    fn is_enabled(&self, global: &dyn Global) -> bool {
+        let name = global.interface().name;
+        if self.disabled.iter().any(|disabled| disabled == name) {
+            return false;
+        }
+        self.enabled.iter().any(|enabled| enabled == name) || global.enabled_by_default()
+    }
+}
+
+struct Entry {
+    global: Arc<dyn Global>,
+    /// The numeric name clients bind with, while the global is advertised.
+    /// A global that comes back gets a new one; names are never reused.
+    name: Option<u32>,
+}
+
+/// A change to the advertised globals, to announce to every client.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GlobalChange {
+    Added { name: u32, interface: &'static str, version: u32 },
+    Removed { name: u32 },
+}
+
+/// The compositor-wide list of globals, shared by every client.
+/// This is the Rust equivalent of GlobalObjects.ts.
+pub struct GlobalRegistry {
+    // Every global the filter lets in, advertised or not.
+    entries: Vec<Entry>,
+    filter: GlobalFilter,
+    // Interfaces passed to `register`, to catch typos in the filter.
+    known: HashSet<&'static str>,
+    // Names that were withdrawn. Clients may still bind them until they
+    // have seen global_remove, so those binds are ignored instead of failing.
+    removed: HashSet<u32>,
+    next_name: u32,
+}
+
+impl GlobalRegistry {
+    pub fn new(filter: GlobalFilter) -> Self {
+        Self {
+            entries: Vec::new(),
+            filter,
+            known: HashSet::new(),
+            removed: HashSet::new(),
+            next_name: 1,
+        }
+    }
+
+    /// Adds a global at startup, unless the filter leaves it out.
+    /// Returns its name if it is advertised right away.
+    pub fn register(&mut self, global: impl Global + 'static) -> Option<u32> {
+        let interface = global.interface();
+        self.known.insert(interface.name);
+        if !self.filter.is_enabled(&global) {
+            eprintln!("[GlobalRegistry] {} is disabled", interface.name);
+            return None;
+        }
+        assert!(
+            global.version() <= interface.version,
+            "{} version {} is newer than the generated bindings ({})",
+            interface.name, global.version(), interface.version
+        );
+        let available = global.is_available();
+        self.entries.push(Entry { global: Arc::new(global), name: None });
+        available.then(|| self.advertise(self.entries.len() - 1))
+    }
+
+    fn advertise(&mut self, index: usize) -> u32 {
+        let name = self.next_name;
+        self.next_name += 1;
+        self.entries[index].name = Some(name);
+        name
+    }
+
+    /// Names in the filter that no registered global has.
+    pub fn unknown_filter_names(&self) -> Vec<&str> {
+        self.filter
+            .enabled
+            .iter()
+            .chain(&self.filter.disabled)
+            .map(String::as_str)
+            .filter(|name| !self.known.contains(name))
+            .collect()
+    }
+
+    /// Advertises the globals that became available and withdraws the ones
+    /// that went away since the last call.
+    pub fn update_availability(&mut self) -> Vec<GlobalChange> {
+        let mut changes = Vec::new();
+        for index in 0..self.entries.len() {
+            let entry = &self.entries[index];
+            match (entry.name, entry.global.is_available()) {
+                (None, true) => {
+                    let name = self.advertise(index);
+                    let global = &self.entries[index].global;
+                    changes.push(GlobalChange::Added { name, interface: global.interface().name, version: global.version() });
+                }
+                (Some(name), false) => {
+                    self.entries[index].name = None;
+                    self.removed.insert(name);
+                    changes.push(GlobalChange::Removed { name });
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// The global is shared out so it can be bound without holding the registry lock.
+    pub fn get(&self, name: u32) -> Option<Arc<dyn Global>> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == Some(name))
+            .map(|entry| Arc::clone(&entry.global))
+    }
+
+    pub fn was_removed(&self, name: u32) -> bool {
+        self.removed.contains(&name)
+    }
+
+    /// (name, interface, version) of every advertised global, in the order they were registered.
+    pub fn list(&self) -> Vec<(u32, &'static str, u32)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some((entry.name?, entry.global.interface().name, entry.global.version())))
+            .collect()
+    }
+}
+
+/// Announces the globals that became available or went away to every
+/// connected client. The render loop calls this once per frame.
+pub fn update_globals(globals: &Mutex<GlobalRegistry>, clients: &[Arc<Mutex<WaylandClient>>]) {
+    // The registry lock is released before locking clients; clients lock it while dispatching.
+    let changes = globals.lock().unwrap().update_availability();
+    if changes.is_empty() {
+        return;
+    }
+    for client in clients {
+        let mut client = client.lock().unwrap();
+        for change in &changes {
+            match *change {
+                GlobalChange::Added { name, interface, version } => client.send_global(name, interface, version),
+                GlobalChange::Removed { name } => client.send_global_remove(name),
+            }
+        }
+    }
+}
@@ -4,85 +4,193 @@ use std::sync::{Arc, Mutex};
 use tokio::process::Command;
 
 // Module declarations
+mod benchmark;
+mod blend;
+mod file_descriptor_claim;
+mod get_message_and_file_descriptors;
+mod global_registry;
+mod message_decoder;
+mod message_encoder;
 mod protocols;
+mod region;
+mod renderer;
+mod scene;
+mod send_message_and_file_descriptors;
+mod shm_pool_memory;
 mod wayland_client;
 mod wayland_object;
+mod wayland_socket_listener;
+mod wayland_types;
 mod terminal_window;
 
 // Use statements
-use wayland_client::WaylandClient;
+use global_registry::{GlobalFilter, GlobalRegistry};
 use terminal_window::{TerminalWindow, AppState};
+use wayland_socket_listener::{WaylandDisplay, WaylandSocketListener};
 
 /// Represents the command-line arguments.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(long, default_value = "wayland-1")]
-    wayland_display_name: String,
+    /// Defaults to $WAYLAND_DISPLAY_NAME, or else the first free wayland-N starting at wayland-2.
+    #[arg(long)]
+    wayland_display_name: Option<String>,
+    /// Optional globals to advertise, by interface name (comma separated).
+    /// Defaults to $TERM_EVERYTHING_ENABLE_GLOBALS.
+    #[arg(long, value_delimiter = ',')]
+    enable_globals: Vec<String>,
+    /// Globals not to advertise, by interface name (comma separated).
+    /// Defaults to $TERM_EVERYTHING_DISABLE_GLOBALS.
+    #[arg(long, value_delimiter = ',')]
+    disable_globals: Vec<String>,
+    /// Measure the compositor's blending and pixel conversion speed, then exit.
+    #[arg(long)]
+    benchmark: bool,
     #[arg(long, default_value = "/bin/bash")]
     shell: String,
     #[arg(allow_hyphen_values = true)]
     positionals: Vec<String>,
 }
 
+/// A comma separated list from the command line, or else from the environment.
+fn list_arg_or_env(arg: Vec<String>, env_name: &str) -> Vec<String> {
+    if !arg.is_empty() {
+        return arg;
+    }
+    std::env::var(env_name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Builds the globals advertised to clients. In-house protocols register
+/// their `Global` here, after the core ones.
+fn create_global_registry(filter: GlobalFilter) -> GlobalRegistry {
+    let mut globals = GlobalRegistry::new(filter);
+    protocols::register_core_globals(&mut globals);
+    for name in globals.unknown_filter_names() {
+        eprintln!("Warning: no global named '{}' to enable or disable", name);
+    }
+    globals
+}
+
 /// This is a conceptual loop for handling input from stdin.
 async fn input_loop(_app_state: Arc<Mutex<AppState>>) {
-    println!("Input loop started (stub).");
+    eprintln!("Input loop started (stub).");
     // This loop would read stdin and call methods on app_state to dispatch events.
 }
 
 
+/// Resolves on any of the signals on_exit.ts cleans up for,
+/// so the display's socket and lock file get removed.
+async fn wait_for_exit_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut quit = signal(SignalKind::quit())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut user_defined1 = signal(SignalKind::user_defined1())?;
+    let mut user_defined2 = signal(SignalKind::user_defined2())?;
+    tokio::select! {
+        _ = interrupt.recv() => {}
+        _ = quit.recv() => {}
+        _ = terminate.recv() => {}
+        _ = hangup.recv() => {}
+        _ = user_defined1.recv() => {}
+        _ = user_defined2.recv() => {}
+    }
+    Ok(())
+}
+
+
 // --- Main Application Logic ---
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    if args.benchmark {
+        process::exit(if benchmark::run() { 0 } else { 1 });
+    }
+
+    let global_filter = GlobalFilter {
+        enabled: list_arg_or_env(args.enable_globals.clone(), "TERM_EVERYTHING_ENABLE_GLOBALS"),
+        disabled: list_arg_or_env(args.disable_globals.clone(), "TERM_EVERYTHING_DISABLE_GLOBALS"),
+    };
 
     // The shared state for the entire application.
     let app_state = Arc::new(Mutex::new(AppState {
         clients: Vec::new(),
+        globals: Arc::new(Mutex::new(create_global_registry(global_filter))),
     }));
 
-    // Create and run the main rendering window.
-    let mut terminal_window = TerminalWindow::new(Arc::clone(&app_state));
+    // Create and run the main rendering window. The kitty renderer waits up to
+    // a second for the terminal to answer its query, so not on a worker.
+    let renderer = tokio::task::spawn_blocking(renderer::from_env)
+        .await
+        .expect("picking a renderer panicked");
+    let mut terminal_window = TerminalWindow::new(Arc::clone(&app_state), renderer);
     let terminal_handle = tokio::spawn(async move {
         terminal_window.main_loop().await;
     });
 
-    // This is the main server loop for accepting new Wayland clients.
-    let server_handle = tokio::spawn({
-        let app_state = Arc::clone(&app_state);
-        async move {
-            println!("Wayland server starting on socket '{}' (simulated).", &args.wayland_display_name);
-            // In a real app, this would be a loop calling `accept()`.
-            // For now, we simulate one client connecting.
-            let mut client = WaylandClient::new(1, 99);
-            app_state.lock().unwrap().clients.push(Arc::new(Mutex::new(client)));
-            // In a real app, we would spawn a task for each client's main_loop.
-            println!("Client handler spawned (stub).");
+    let display_name = args
+        .wayland_display_name
+        .clone()
+        .or_else(|| std::env::var("WAYLAND_DISPLAY_NAME").ok());
+    // Dropping the display removes its socket and lock file.
+    let display = match WaylandDisplay::acquire(display_name.as_deref()) {
+        Ok(display) => display,
+        Err(e) => {
+            eprintln!("Failed to take a wayland display: {}", e);
+            process::exit(1);
         }
-    });
+    };
+
+    // Bind before spawning anything, so the socket exists when the command starts.
+    let listener = match WaylandSocketListener::new(&display) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen to wayland socket '{}': {}", display.name, e);
+            drop(display);
+            process::exit(1);
+        }
+    };
+
+    // This is the main server loop for accepting new Wayland clients.
+    let server_handle = tokio::spawn(listener.main_loop(Arc::clone(&app_state)));
 
     // Spawn the conceptual input loop
     let input_handle = tokio::spawn(input_loop(Arc::clone(&app_state)));
 
-    println!("All main loops (server, renderer, input) are running.");
+    eprintln!("All main loops (server, renderer, input) are running.");
 
     if !args.positionals.is_empty() {
         let mut command = Command::new(&args.shell);
         command.arg("-c").arg(args.positionals.join(" "));
-        command.env("WAYLAND_DISPLAY", &args.wayland_display_name);
+        command.env("WAYLAND_DISPLAY", &display.name);
 
         match command.spawn() {
-            Ok(child) => println!("Successfully spawned command with PID: {:?}", child.id()),
+            Ok(child) => eprintln!("Successfully spawned command with PID: {:?}", child.id()),
             Err(e) => {
                 eprintln!("Failed to spawn command: {}", e);
+                drop(display);
                 process::exit(1);
             }
         }
     }
 
-    println!("Compositor running. Waiting for tasks to finish.");
-    let _ = tokio::try_join!(server_handle, terminal_handle, input_handle);
-    println!("Compositor shutting down.");
+    eprintln!("Compositor running. Waiting for tasks to finish.");
+    tokio::select! {
+        _ = async { tokio::try_join!(server_handle, terminal_handle, input_handle) } => {}
+        _ = wait_for_exit_signal() => {}
+    }
+    eprintln!("Compositor shutting down.");
+    drop(display);
 }
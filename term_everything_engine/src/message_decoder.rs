@@ -0,0 +1,365 @@
+use std::fmt;
+
+use crate::file_descriptor_claim::FileDescriptorClaim;
+use crate::wayland_types::{Argument, ArgumentType, Fixed};
+
+/// Every message starts with an 8 byte header:
+/// - 4 bytes for the object id
+/// - 4 bytes holding the size of the whole message, header included, in the
+///   upper 16 bits and the opcode in the lower 16 bits
+const HEADER_SIZE: usize = 8;
+
+/// A complete message whose arguments have not been decoded yet.
+/// The argument layout depends on the interface of the target object,
+/// which only the client knows.
+pub struct Message {
+    pub object_id: u32,
+    pub opcode: u16,
+    pub data: Vec<u8>,
+}
+
+/// Streaming decoder for the Wayland wire format.
+/// This is the Rust equivalent of Message_Decoder.ts.
+///
+/// Bytes may arrive in arbitrary chunks: a single read can contain several
+/// messages, or stop in the middle of one. Incomplete bytes are kept until the
+/// rest of the message arrives.
+#[derive(Default)]
+pub struct MessageDecoder {
+    pending: Vec<u8>,
+}
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Feeds freshly read bytes into the decoder and returns every
+    /// message that is now complete, in the order they were sent.
+    /// A header with an impossible size is an error; the stream can not be
+    /// resynchronized after it, so everything buffered is dropped.
+    pub fn consume(&mut self, buffer: &[u8]) -> Result<Vec<Message>, DecodeError> {
+        self.pending.extend_from_slice(buffer);
+
+        let mut new_messages = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= HEADER_SIZE {
+            let header = &self.pending[start..start + HEADER_SIZE];
+            let object_id = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+            let word = u32::from_ne_bytes([header[4], header[5], header[6], header[7]]);
+            let opcode = (word & 0xffff) as u16;
+            let size = (word >> 16) as usize;
+
+            // The size includes the header, and every argument is padded to 32 bits.
+            if size < HEADER_SIZE || !size.is_multiple_of(4) {
+                self.pending.clear();
+                return Err(DecodeError::InvalidSize { object_id, opcode, size });
+            }
+
+            if self.pending.len() - start < size {
+                // Wait for the rest of the message.
+                break;
+            }
+
+            new_messages.push(Message {
+                object_id,
+                opcode,
+                data: self.pending[start + HEADER_SIZE..start + size].to_vec(),
+            });
+            start += size;
+        }
+
+        self.pending.drain(..start);
+        Ok(new_messages)
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// A message header with a size below the header size or not a multiple of 4.
+    InvalidSize { object_id: u32, opcode: u16, size: usize },
+    /// The message ended before all arguments of the signature were read.
+    Truncated,
+    /// A string argument was not NUL terminated or not valid UTF-8.
+    InvalidString,
+    /// The signature has an `fd` argument but no file descriptor was received for it.
+    MissingFileDescriptor,
+    /// A string, object or new_id argument that is not nullable was null.
+    NullArgument,
+    /// The decoded arguments do not match the types of the request.
+    TypeMismatch,
+    /// The interface has no request with this opcode.
+    UnknownOpcode(u16),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidSize { object_id, opcode, size } => {
+                write!(f, "invalid size {} for object {} opcode {}", size, object_id, opcode)
+            }
+            DecodeError::Truncated => write!(f, "message is shorter than its signature"),
+            DecodeError::InvalidString => write!(f, "invalid string argument"),
+            DecodeError::MissingFileDescriptor => write!(f, "missing file descriptor"),
+            DecodeError::NullArgument => write!(f, "null value for a non-nullable argument"),
+            DecodeError::TypeMismatch => write!(f, "argument type does not match the request"),
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {}", opcode),
+        }
+    }
+}
+
+/// Decodes the payload of a message according to its signature.
+/// File descriptors are not part of the payload; they are claimed in order
+/// from the connection's queue of received descriptors.
+pub fn decode_arguments(
+    signature: &[ArgumentType],
+    data: &[u8],
+    file_descriptors: &mut dyn FileDescriptorClaim,
+) -> Result<Vec<Argument>, DecodeError> {
+    let mut reader = WordReader { data, position: 0 };
+    let mut args = Vec::with_capacity(signature.len());
+    for arg_type in signature {
+        let arg = match arg_type {
+            ArgumentType::Int => Argument::Int(reader.word()? as i32),
+            ArgumentType::Uint => Argument::Uint(reader.word()?),
+            ArgumentType::Fixed => Argument::Fixed(Fixed(reader.word()? as i32)),
+            ArgumentType::Object => {
+                let id = reader.word()?;
+                Argument::Object(if id == 0 { None } else { Some(id) })
+            }
+            ArgumentType::NewId => Argument::NewId(reader.word()?),
+            ArgumentType::String => {
+                // The length includes the terminating NUL; a length of 0 is a null string.
+                let length = reader.word()? as usize;
+                if length == 0 {
+                    Argument::String(None)
+                } else {
+                    let bytes = reader.bytes(length)?;
+                    let (last, text) = bytes.split_last().ok_or(DecodeError::InvalidString)?;
+                    if *last != 0 {
+                        return Err(DecodeError::InvalidString);
+                    }
+                    let text = std::str::from_utf8(text).map_err(|_| DecodeError::InvalidString)?;
+                    Argument::String(Some(text.to_string()))
+                }
+            }
+            ArgumentType::Array => {
+                let length = reader.word()? as usize;
+                Argument::Array(reader.bytes(length)?.to_vec())
+            }
+            ArgumentType::Fd => {
+                let fd = file_descriptors.claim_file_descriptor();
+                Argument::Fd(fd.ok_or(DecodeError::MissingFileDescriptor)?)
+            }
+        };
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+struct WordReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WordReader<'a> {
+    fn word(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads `length` bytes and skips the padding up to the next 32-bit boundary.
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let padded = length.checked_add(3).ok_or(DecodeError::Truncated)? & !3;
+        if self.data.len() - self.position < padded {
+            return Err(DecodeError::Truncated);
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += padded;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(object_id: u32, opcode: u16, payload: &[u8]) -> Vec<u8> {
+        let size = (HEADER_SIZE + payload.len()) as u32;
+        let mut bytes = object_id.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn decodes_header_fields() {
+        let mut decoder = MessageDecoder::new();
+        let messages = decoder.consume(&message(3, 7, &[1, 2, 3, 4])).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].object_id, 3);
+        assert_eq!(messages[0].opcode, 7);
+        assert_eq!(messages[0].data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn decodes_several_messages_in_one_read() {
+        let mut bytes = message(1, 0, &[]);
+        bytes.extend(message(2, 1, &[9; 8]));
+        bytes.extend(message(3, 2, &[5; 4]));
+        let mut decoder = MessageDecoder::new();
+        let messages = decoder.consume(&bytes).unwrap();
+        let ids: Vec<(u32, u16, usize)> = messages.iter().map(|m| (m.object_id, m.opcode, m.data.len())).collect();
+        assert_eq!(ids, [(1, 0, 0), (2, 1, 8), (3, 2, 4)]);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_split_message() {
+        let bytes = message(4, 1, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut decoder = MessageDecoder::new();
+        assert!(decoder.consume(&bytes[..11]).unwrap().is_empty());
+        let messages = decoder.consume(&bytes[11..]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_partial_header() {
+        let mut bytes = message(1, 0, &[]);
+        bytes.extend(message(5, 3, &[7; 4]));
+        let mut decoder = MessageDecoder::new();
+        // The first message and half of the second header.
+        let messages = decoder.consume(&bytes[..12]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(decoder.consume(&bytes[12..14]).unwrap().is_empty());
+        let messages = decoder.consume(&bytes[14..]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!((messages[0].object_id, messages[0].opcode), (5, 3));
+    }
+
+    #[test]
+    fn feeds_one_byte_at_a_time() {
+        let mut bytes = message(1, 0, &[1; 4]);
+        bytes.extend(message(2, 1, &[]));
+        let mut decoder = MessageDecoder::new();
+        let mut messages = Vec::new();
+        for byte in &bytes {
+            messages.extend(decoder.consume(std::slice::from_ref(byte)).unwrap());
+        }
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].object_id, 2);
+    }
+
+    #[test]
+    fn rejects_a_size_smaller_than_the_header() {
+        let mut bytes = 1u32.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&((4u32 << 16) | 2).to_ne_bytes());
+        let mut decoder = MessageDecoder::new();
+        assert!(matches!(
+            decoder.consume(&bytes),
+            Err(DecodeError::InvalidSize { object_id: 1, opcode: 2, size: 4 })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_size_that_is_not_a_multiple_of_4() {
+        let mut bytes = 1u32.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&((10u32 << 16) | 2).to_ne_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        let mut decoder = MessageDecoder::new();
+        assert!(matches!(decoder.consume(&bytes), Err(DecodeError::InvalidSize { size: 10, .. })));
+        // Nothing of the broken stream is kept.
+        assert_eq!(decoder.consume(&message(2, 0, &[])).unwrap().len(), 1);
+    }
+
+    /// No file descriptors were received.
+    struct NoFileDescriptors;
+
+    impl FileDescriptorClaim for NoFileDescriptors {
+        fn claim_file_descriptor(&mut self) -> Option<std::os::unix::io::OwnedFd> {
+            None
+        }
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_ne_bytes()).collect()
+    }
+
+    fn decode(signature: &[ArgumentType], data: &[u8]) -> Result<Vec<Argument>, DecodeError> {
+        decode_arguments(signature, data, &mut NoFileDescriptors)
+    }
+
+    #[test]
+    fn decodes_padded_strings() {
+        // "hello" and its NUL take 6 bytes, padded to 8, then a uint.
+        let mut data = words(&[6]);
+        data.extend_from_slice(b"hello\0\0\0");
+        data.extend(words(&[42]));
+        let args = decode(&[ArgumentType::String, ArgumentType::Uint], &data).unwrap();
+        assert!(matches!(&args[0], Argument::String(Some(text)) if text == "hello"));
+        assert!(matches!(args[1], Argument::Uint(42)));
+    }
+
+    #[test]
+    fn decodes_strings_that_fill_a_word() {
+        let mut data = words(&[4]);
+        data.extend_from_slice(b"abc\0");
+        data.extend(words(&[7]));
+        let args = decode(&[ArgumentType::String, ArgumentType::Int], &data).unwrap();
+        assert!(matches!(&args[0], Argument::String(Some(text)) if text == "abc"));
+        assert!(matches!(args[1], Argument::Int(7)));
+    }
+
+    #[test]
+    fn decodes_null_strings() {
+        let args = decode(&[ArgumentType::String, ArgumentType::Uint], &words(&[0, 5])).unwrap();
+        assert!(matches!(args[0], Argument::String(None)));
+        assert!(matches!(args[1], Argument::Uint(5)));
+        assert!(matches!(args.into_iter().next().unwrap().into_string(), Err(DecodeError::NullArgument)));
+    }
+
+    #[test]
+    fn rejects_strings_without_a_terminating_nul() {
+        let mut data = words(&[4]);
+        data.extend_from_slice(b"abcd");
+        assert!(matches!(decode(&[ArgumentType::String], &data), Err(DecodeError::InvalidString)));
+    }
+
+    #[test]
+    fn decodes_padded_arrays() {
+        let mut data = words(&[5]);
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 0, 0, 0]);
+        data.extend(words(&[9]));
+        let args = decode(&[ArgumentType::Array, ArgumentType::Uint], &data).unwrap();
+        assert!(matches!(&args[0], Argument::Array(bytes) if bytes == &[1, 2, 3, 4, 5]));
+        assert!(matches!(args[1], Argument::Uint(9)));
+    }
+
+    #[test]
+    fn decodes_empty_arrays() {
+        let args = decode(&[ArgumentType::Array], &words(&[0])).unwrap();
+        assert!(matches!(&args[0], Argument::Array(bytes) if bytes.is_empty()));
+    }
+
+    #[test]
+    fn decodes_fixed() {
+        // 1.5 and -2.25 in 24.8 fixed point.
+        let data = words(&[384, (-576i32) as u32]);
+        let args = decode(&[ArgumentType::Fixed, ArgumentType::Fixed], &data).unwrap();
+        assert!(matches!(args[0], Argument::Fixed(Fixed(384))));
+        assert!(matches!(args[1], Argument::Fixed(Fixed(-576))));
+    }
+
+    #[test]
+    fn rejects_truncated_arguments() {
+        let mut data = words(&[8]);
+        data.extend_from_slice(b"abc");
+        assert!(matches!(decode(&[ArgumentType::String], &data), Err(DecodeError::Truncated)));
+        assert!(matches!(decode(&[ArgumentType::Uint, ArgumentType::Uint], &words(&[1])), Err(DecodeError::Truncated)));
+    }
+
+    #[test]
+    fn reports_missing_file_descriptors() {
+        assert!(matches!(decode(&[ArgumentType::Fd], &[]), Err(DecodeError::MissingFileDescriptor)));
+    }
+}
@@ -0,0 +1,66 @@
+use std::os::unix::io::OwnedFd;
+
+use crate::wayland_object::Event;
+use crate::wayland_types::Argument;
+
+/// Serializes an event into the Wayland wire format.
+/// The header is the same as for requests (object id, opcode, size);
+/// file descriptor arguments are not written to the payload but moved
+/// to `fds`, to be sent as SCM_RIGHTS along with the bytes.
+pub fn encode_event(event: Event, out: &mut Vec<u8>, fds: &mut Vec<OwnedFd>) {
+    let start = out.len();
+    out.extend_from_slice(&event.object_id.to_ne_bytes());
+    // The size goes in the upper 16 bits of this word; it is patched in
+    // once the payload is written.
+    out.extend_from_slice(&(event.opcode as u32).to_ne_bytes());
+
+    for arg in event.args {
+        match arg {
+            Argument::Int(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Argument::Uint(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Argument::Fixed(value) => out.extend_from_slice(&value.0.to_ne_bytes()),
+            Argument::Object(value) => out.extend_from_slice(&value.unwrap_or(0).to_ne_bytes()),
+            Argument::NewId(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Argument::String(None) => out.extend_from_slice(&0u32.to_ne_bytes()),
+            Argument::String(Some(text)) => {
+                // The length includes the terminating NUL.
+                out.extend_from_slice(&(text.len() as u32 + 1).to_ne_bytes());
+                out.extend_from_slice(text.as_bytes());
+                out.push(0);
+                pad_to_word(out);
+            }
+            Argument::Array(bytes) => {
+                out.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
+                out.extend_from_slice(&bytes);
+                pad_to_word(out);
+            }
+            Argument::Fd(fd) => fds.push(fd),
+        }
+    }
+
+    let size = (out.len() - start) as u32;
+    let word = (size << 16) | event.opcode as u32;
+    out[start + 4..start + 8].copy_from_slice(&word.to_ne_bytes());
+}
+
+fn pad_to_word(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_decoder::MessageDecoder;
+
+    #[test]
+    fn encodes_a_header_the_decoder_reads_back() {
+        let mut out = Vec::new();
+        let event = Event::new(7, 3, vec![Argument::String(Some("hi".to_string())), Argument::Array(vec![1, 2, 3, 4, 5])]);
+        encode_event(event, &mut out, &mut Vec::new());
+        // Header, "hi\0" padded to 4, then 5 bytes padded to 8.
+        assert_eq!(out.len(), 8 + 4 + 4 + 4 + 8);
+        let messages = MessageDecoder::new().consume(&out).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!((messages[0].object_id, messages[0].opcode, messages[0].data.len()), (7, 3, 20));
+    }
+}
@@ -1,6 +1,8 @@
 // This file makes the `protocols` directory a module.
 
 pub mod wl_display;
+pub mod wl_callback;
+pub mod wl_registry;
 pub mod wl_compositor;
 pub mod wl_surface;
 pub mod wl_shm;
@@ -9,7 +11,28 @@ pub mod wl_buffer;
 pub mod xdg_wm_base;
 pub mod xdg_surface;
 pub mod xdg_toplevel;
+pub mod xdg_popup;
+pub mod xdg_positioner;
+pub mod wl_subcompositor;
+pub mod wl_subsurface;
 pub mod wl_seat;
 pub mod wl_keyboard;
 pub mod wl_pointer;
 // I will add other protocols here as I create them.
+
+use crate::global_registry::GlobalRegistry;
+
+/// Registers the globals implemented in this module.
+pub fn register_core_globals(globals: &mut GlobalRegistry) {
+    globals.register(wl_compositor::WlCompositorGlobal);
+    globals.register(wl_shm::WlShmGlobal);
+    globals.register(wl_subcompositor::WlSubcompositorGlobal);
+    globals.register(xdg_wm_base::XdgWmBaseGlobal);
+    globals.register(wl_seat::WlSeatGlobal);
+}
+
+/// Bindings generated by build.rs from `scripts/generate_protocol/protocols`.
+#[allow(dead_code, clippy::too_many_arguments)]
+pub mod generated {
+    include!(concat!(env!("OUT_DIR"), "/protocols.rs"));
+}
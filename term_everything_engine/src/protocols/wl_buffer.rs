@@ -1,67 +1,102 @@
-use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use std::sync::{Arc, Mutex};
 
-type WlShmPoolId = u32;
+use crate::protocols::generated::{wl_buffer::Request, wl_shm};
+use crate::protocols::wl_shm::{bytes_per_pixel, convert_row_to_rgba};
+use crate::region::Rect;
+use crate::shm_pool_memory::ShmPoolMemory;
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
 /// Implements the wl_buffer object.
 /// A wl_buffer represents a block of memory shared between the client and server.
-/// This object is mostly a handle with metadata.
+/// This object is a view into the memory of the pool it was created from.
 pub struct WlBuffer {
-    // A reference back to the pool that created this buffer.
-    parent_pool_id: WlShmPoolId,
+    // The pool's mapping; keeps it alive after the pool itself is destroyed.
+    memory: Arc<Mutex<ShmPoolMemory>>,
     // Metadata about this specific buffer's view into the pool's memory.
-    offset: i32,
+    // Checked against the pool size when the buffer was created.
+    offset: usize,
     width: i32,
     height: i32,
     stride: i32,
-    // format: u32,
+    // One of wl_shm::SUPPORTED_FORMATS, checked by the pool.
+    format: u32,
 }
 
 impl WlBuffer {
     pub fn new(
-        parent_pool_id: WlShmPoolId,
-        offset: i32,
+        memory: Arc<Mutex<ShmPoolMemory>>,
+        offset: usize,
         width: i32,
         height: i32,
         stride: i32,
-        _format: u32,
+        format: u32,
     ) -> Self {
         Self {
-            parent_pool_id,
+            memory,
             offset,
             width,
             height,
             stride,
-            // format,
+            format,
         }
     }
 
-    fn handle_destroy(&self, client: &mut WaylandClient, self_id: u32) {
-        println!("[wl_buffer] destroy: buffer {}", self_id);
+    /// Calls `f` with the buffer's bytes, `stride * height` of them.
+    /// Fails if the client truncated the pool's file, and then the client has to go.
+    pub fn with_contents<R>(&self, buffer_id: u32, f: impl FnOnce(&[u8]) -> R) -> Result<R, ProtocolError> {
+        let memory = self.memory.lock().unwrap();
+        let len = self.stride as usize * self.height as usize;
+        // Pools never shrink, so this stays in bounds unless the file itself shrank.
+        memory.read(self.offset, len, f).map_err(|e| {
+            ProtocolError::new(
+                buffer_id,
+                wl_shm::error::INVALID_FD,
+                format!("error accessing wl_buffer {}: {}", buffer_id, e),
+            )
+        })
+    }
+
+    /// The whole buffer, in buffer coordinates.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
 
-        // This is the tricky part. We need to get a mutable reference to the parent pool
-        // and call a method on it. This requires careful architecture in Rust to avoid
-        // borrowing issues (e.g., using interior mutability like RefCell, or structuring
-        // the main loop to handle this).
+    /// Copies the buffer out as RGBA8, `width * 4` bytes per row.
+    pub fn to_rgba(&self, buffer_id: u32) -> Result<Vec<u8>, ProtocolError> {
+        let mut texture = vec![0; self.width as usize * 4 * self.height as usize];
+        self.copy_to_rgba(buffer_id, &[self.bounds()], &mut texture)?;
+        Ok(texture)
+    }
 
-        // For this translation, we'll represent it conceptually.
-        client.signal_buffer_destroyed(self.parent_pool_id, self_id);
+    /// Copies only `rects` into a texture the size of the buffer, as RGBA8.
+    /// The rects must be inside `bounds()`.
+    pub fn copy_to_rgba(&self, buffer_id: u32, rects: &[Rect], texture: &mut [u8]) -> Result<(), ProtocolError> {
+        let (texture_stride, stride) = (self.width as usize * 4, self.stride as usize);
+        let bytes_per_pixel = bytes_per_pixel(self.format).unwrap() as usize;
+        self.with_contents(buffer_id, |contents| {
+            for rect in rects {
+                let (x, width) = (rect.x as usize, rect.width as usize);
+                for y in rect.y as usize..(rect.y + rect.height) as usize {
+                    let src = &contents[y * stride + x * bytes_per_pixel..][..width * bytes_per_pixel];
+                    let dst = &mut texture[y * texture_stride + x * 4..][..width * 4];
+                    convert_row_to_rgba(self.format, src, dst);
+                }
+            }
+        })
     }
 }
 
-impl WaylandObject for WlBuffer {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const DESTROY: u16 = 0;
+impl RequestHandler for WlBuffer {
+    type Request = Request;
 
-        match request.opcode {
-            DESTROY => {
-                self.handle_destroy(client, request.object_id);
-                // After destroy, the object should be removed from the client's map.
-                // The main loop would handle this.
-            }
-            _ => {
-                eprintln!("[wl_buffer] Received unknown opcode: {}", request.opcode);
+    fn handle_request(&mut self, _client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::Destroy => {
+                eprintln!("[wl_buffer] destroy: buffer {}", object_id);
             }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,28 @@
+use crate::protocols::generated::wl_callback::{self, Request};
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+
+/// Implements the wl_callback object, used by wl_surface.frame and wl_display.sync.
+/// It has no requests; it lives until `done` is sent.
+pub struct WlCallback;
+
+impl WlCallback {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Sends wl_callback.done and destroys the callback, which the event does implicitly.
+    pub fn done(client: &mut WaylandClient, callback_id: u32, callback_data: u32) {
+        wl_callback::send_done(client, callback_id, callback_data);
+        client.destroy_object(callback_id);
+    }
+}
+
+impl RequestHandler for WlCallback {
+    type Request = Request;
+
+    fn handle_request(&mut self, _client: &mut WaylandClient, _object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {}
+    }
+}
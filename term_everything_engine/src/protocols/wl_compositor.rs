@@ -1,15 +1,15 @@
+use crate::protocols::generated::{wl_compositor, wl_region};
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::global_registry::Global;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::{Interface, ObjectId};
+use crate::protocols::wl_surface::WlSurface;
 
 // --- Placeholders for objects created by the compositor ---
-pub struct WlSurface;
-impl WaylandObject for WlSurface {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
-}
-
 pub struct WlRegion;
-impl WaylandObject for WlRegion {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
+impl RequestHandler for WlRegion {
+    type Request = wl_region::Request;
+    fn handle_request(&mut self, _client: &mut WaylandClient, _object_id: ObjectId, _request: wl_region::Request) -> Result<(), ProtocolError> { Ok(()) }
 }
 // --- End Placeholders ---
 
@@ -24,38 +24,47 @@ impl WlCompositor {
     }
 
     fn handle_create_surface(&self, client: &mut WaylandClient, new_surface_id: u32) {
-        println!("[wl_compositor] Handling create_surface, creating surface {}", new_surface_id);
-        let surface = WlSurface {};
+        eprintln!("[wl_compositor] Handling create_surface, creating surface {}", new_surface_id);
+        let surface = WlSurface::new(new_surface_id);
         client.add_object(new_surface_id, Box::new(surface));
     }
 
     fn handle_create_region(&self, client: &mut WaylandClient, new_region_id: u32) {
-        println!("[wl_compositor] Handling create_region, creating region {}", new_region_id);
+        eprintln!("[wl_compositor] Handling create_region, creating region {}", new_region_id);
         let region = WlRegion {};
         client.add_object(new_region_id, Box::new(region));
     }
 }
 
-impl WaylandObject for WlCompositor {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        // Opcodes for wl_compositor requests from the protocol XML.
-        const CREATE_SURFACE: u16 = 0;
-        const CREATE_REGION: u16 = 1;
-
-        match request.opcode {
-            CREATE_SURFACE => {
-                if let Some(new_id) = request.args.get(0) {
-                    self.handle_create_surface(client, *new_id);
-                }
-            }
-            CREATE_REGION => {
-                if let Some(new_id) = request.args.get(0) {
-                    self.handle_create_region(client, *new_id);
-                }
+impl RequestHandler for WlCompositor {
+    type Request = wl_compositor::Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, _object_id: ObjectId, request: wl_compositor::Request) -> Result<(), ProtocolError> {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                self.handle_create_surface(client, id);
             }
-            _ => {
-                eprintln!("[wl_compositor] Received unknown opcode: {}", request.opcode);
+            wl_compositor::Request::CreateRegion { id } => {
+                self.handle_create_region(client, id);
             }
         }
+        Ok(())
+    }
+}
+
+/// Advertises wl_compositor.
+pub struct WlCompositorGlobal;
+
+impl Global for WlCompositorGlobal {
+    fn interface(&self) -> &'static Interface {
+        &wl_compositor::INTERFACE
+    }
+
+    fn version(&self) -> u32 {
+        4
+    }
+
+    fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+        client.add_object(id, Box::new(WlCompositor::new()));
     }
 }
@@ -1,58 +1,78 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{FromRawFd, OwnedFd};
+
+use crate::protocols::generated::wl_keyboard::{self, keymap_format, Request};
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
-// From the XML protocol file
-mod keymap_format {
-    pub const NO_KEYMAP: u32 = 0;
-    pub const XKB_V1: u32 = 1;
-}
+// The same keymap the TypeScript compositor serves.
+const KEYMAP: &[u8] = include_bytes!("../../../resources/server-1.xkb");
 
 /// Implements the wl_keyboard object.
 /// This object represents a keyboard device and is responsible for
 /// sending the keymap and key events to the client.
 pub struct WlKeyboard {
-    // In a real implementation, the constructor would create a temporary
-    // file with the XKB keymap and store the file descriptor and size here.
-    keymap_fd: i32,
+    // An anonymous file holding the XKB keymap, shared with the client.
+    keymap_fd: Option<OwnedFd>,
     keymap_size: u32,
 }
 
 impl WlKeyboard {
     pub fn new() -> Self {
-        println!("[wl_keyboard] new: preparing keymap (stub)");
-        // Conceptual representation of creating a temp file with the keymap.
-        // These values would come from file system operations.
+        eprintln!("[wl_keyboard] new: preparing keymap");
+        let keymap_fd = match Self::create_keymap_file() {
+            Ok(fd) => Some(fd),
+            Err(e) => {
+                eprintln!("[wl_keyboard] Failed to create keymap file: {}", e);
+                None
+            }
+        };
         Self {
-            keymap_fd: -1, // Invalid FD placeholder
-            keymap_size: 2429, // Size from the original project's resource
+            keymap_fd,
+            // Clients read the keymap as a NUL terminated string.
+            keymap_size: KEYMAP.len() as u32 + 1,
         }
     }
 
+    fn create_keymap_file() -> std::io::Result<OwnedFd> {
+        let fd = unsafe { libc::memfd_create(c"wl_keyboard_keymap".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(KEYMAP)?;
+        file.write_all(&[0])?;
+        Ok(file.into())
+    }
+
     /// This logic is called by wl_seat after a client gets a keyboard.
     pub fn on_get_keyboard(&self, client: &mut WaylandClient, self_id: u32) {
-        println!("[wl_keyboard] on_get_keyboard: sending keymap to client for keyboard {}", self_id);
-
-        // This would send the keymap event to the client.
-        // client.send_event(self_id, WlKeyboard::Events::Keymap {
-        //     format: keymap_format::XKB_V1,
-        //     fd: self.keymap_fd,
-        //     size: self.keymap_size,
-        // });
+        eprintln!("[wl_keyboard] on_get_keyboard: sending keymap to client for keyboard {}", self_id);
+
+        let Some(keymap_fd) = &self.keymap_fd else {
+            eprintln!("[wl_keyboard] No keymap to send to keyboard {}", self_id);
+            return;
+        };
+        // Every client gets its own descriptor; the original stays with the keyboard.
+        match keymap_fd.try_clone() {
+            Ok(fd) => wl_keyboard::send_keymap(client, self_id, keymap_format::XKB_V1, fd, self.keymap_size),
+            Err(e) => eprintln!("[wl_keyboard] Failed to duplicate keymap fd: {}", e),
+        }
     }
 }
 
-impl WaylandObject for WlKeyboard {
-    fn on_request(&mut self, _client: &mut WaylandClient, request: Request) {
-        const RELEASE: u16 = 0;
+impl RequestHandler for WlKeyboard {
+    type Request = Request;
 
-        match request.opcode {
-            RELEASE => {
-                println!("[wl_keyboard] release");
+    fn handle_request(&mut self, _client: &mut WaylandClient, _object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::Release => {
+                eprintln!("[wl_keyboard] release");
                 // The object will be destroyed by the client's main loop.
             }
-            _ => {
-                eprintln!("[wl_keyboard] Received unknown opcode: {}", request.opcode);
-            }
         }
+        Ok(())
     }
 }
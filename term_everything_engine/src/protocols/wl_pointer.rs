@@ -1,6 +1,9 @@
 use std::collections::HashMap;
+use crate::protocols::generated::wl_pointer::{self, Request};
+use crate::protocols::wl_surface::SurfaceRole;
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
 type SurfaceId = u32;
 type ClientId = u32; // Using the client's FD as a unique ID.
@@ -10,12 +13,12 @@ type ClientId = u32; // Using the client's FD as a unique ID.
 /// cursor image and is responsible for sending pointer events.
 #[derive(Default)]
 pub struct WlPointer {
-    // Tracks the cursor surface for each client.
+    // Tracks the cursor surface and its hotspot for each client.
     // The key is a unique client identifier.
-    cursor_surface_per_client: HashMap<ClientId, SurfaceId>,
-    // The global position of the pointer on the virtual screen.
-    x: f64,
-    y: f64,
+    cursor_surface_per_client: HashMap<ClientId, (SurfaceId, i32, i32)>,
+    // The global position of the pointer on the virtual screen. Nothing
+    // reports pointer input yet, so it stays unknown.
+    position: Option<(f64, f64)>,
 }
 
 impl WlPointer {
@@ -26,44 +29,62 @@ impl WlPointer {
     fn handle_set_cursor(
         &mut self,
         client: &mut WaylandClient,
+        pointer_id: ObjectId,
         _serial: u32,
-        surface_id: SurfaceId,
+        surface_id: Option<SurfaceId>,
         hotspot_x: i32,
         hotspot_y: i32
-    ) {
-        println!(
-            "[wl_pointer] set_cursor: surface={}, hotspot=({},{})",
+    ) -> Result<(), ProtocolError> {
+        eprintln!(
+            "[wl_pointer] set_cursor: surface={:?}, hotspot=({},{})",
             surface_id, hotspot_x, hotspot_y
         );
 
-        // Update the cursor surface for this client.
-        self.cursor_surface_per_client.insert(client.get_id(), surface_id);
+        // A null surface hides the cursor.
+        let Some(surface_id) = surface_id else {
+            self.cursor_surface_per_client.remove(&client.get_id());
+            return Ok(());
+        };
 
         // Assign the 'cursor' role to the surface.
-        // This is a conceptual call.
-        client.set_surface_role(surface_id, 0, "cursor");
+        if !client.set_surface_role(surface_id, SurfaceRole::Cursor, None) {
+            return Err(ProtocolError::new(
+                pointer_id,
+                wl_pointer::error::ROLE,
+                format!("wl_surface {} already has another role", surface_id),
+            ));
+        }
+
+        // Update the cursor surface for this client.
+        self.cursor_surface_per_client.insert(client.get_id(), (surface_id, hotspot_x, hotspot_y));
+        Ok(())
+    }
+
+    /// The client's cursor surface and where to draw it, its hotspot on the pointer.
+    /// None until the pointer's position is known.
+    pub fn cursor(&self, client_id: ClientId) -> Option<(SurfaceId, i32, i32)> {
+        let (surface_id, hotspot_x, hotspot_y) = *self.cursor_surface_per_client.get(&client_id)?;
+        let (x, y) = self.position?;
+        Some((surface_id, x as i32 - hotspot_x, y as i32 - hotspot_y))
     }
 }
 
-impl WaylandObject for WlPointer {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const SET_CURSOR: u16 = 0;
-        const RELEASE: u16 = 1;
+impl RequestHandler for WlPointer {
+    type Request = Request;
 
-        match request.opcode {
-            SET_CURSOR => {
-                let serial = request.args.get(0).cloned().unwrap_or(0);
-                let surface_id = request.args.get(1).cloned().unwrap_or(0);
-                let hotspot_x = request.args.get(2).cloned().unwrap_or(0) as i32;
-                let hotspot_y = request.args.get(3).cloned().unwrap_or(0) as i32;
-                self.handle_set_cursor(client, serial, surface_id, hotspot_x, hotspot_y);
-            }
-            RELEASE => {
-                println!("[wl_pointer] release");
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::SetCursor { serial, surface, hotspot_x, hotspot_y } => {
+                self.handle_set_cursor(client, object_id, serial, surface, hotspot_x, hotspot_y)?;
             }
-            _ => {
-                eprintln!("[wl_pointer] Received unknown opcode: {}", request.opcode);
+            Request::Release => {
+                eprintln!("[wl_pointer] release");
             }
         }
+        Ok(())
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, _object_id: ObjectId) {
+        self.cursor_surface_per_client.remove(&client.get_id());
     }
 }
@@ -0,0 +1,79 @@
+use crate::protocols::generated::{wl_display, wl_registry::Request};
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+
+/// Implements the wl_registry object.
+/// Every client gets its own registry objects; the globals they advertise
+/// come from the compositor-wide `GlobalRegistry`.
+pub struct WlRegistry;
+
+impl WlRegistry {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn handle_bind(
+        &self,
+        client: &mut WaylandClient,
+        self_id: ObjectId,
+        name: u32,
+        interface: &str,
+        version: u32,
+        new_id: ObjectId,
+    ) -> Result<(), ProtocolError> {
+        eprintln!("[wl_registry] bind: {} version {} (name {}) as {}", interface, version, name, new_id);
+
+        let global = {
+            let globals = client.globals();
+            let globals = globals.lock().unwrap();
+            match globals.get(name) {
+                Some(global) => global,
+                None if globals.was_removed(name) => {
+                    // The client bound it before it saw global_remove.
+                    eprintln!("[wl_registry] bind: ignoring bind to removed global {}", name);
+                    return Ok(());
+                }
+                None => {
+                    return Err(ProtocolError::new(
+                        self_id,
+                        wl_display::error::INVALID_OBJECT,
+                        format!("invalid global {} ({})", interface, name),
+                    ));
+                }
+            }
+        };
+
+        if global.interface().name != interface {
+            return Err(ProtocolError::new(
+                self_id,
+                wl_display::error::INVALID_OBJECT,
+                format!("invalid interface for global {}: have {}, wanted {}", name, global.interface().name, interface),
+            ));
+        }
+        if version == 0 || version > global.version() {
+            return Err(ProtocolError::new(
+                self_id,
+                wl_display::error::INVALID_OBJECT,
+                format!("invalid version for global {} ({}): have {}, wanted {}", interface, name, global.version(), version),
+            ));
+        }
+
+        client.set_object_version(new_id, version);
+        global.bind(client, new_id, version);
+        Ok(())
+    }
+}
+
+impl RequestHandler for WlRegistry {
+    type Request = Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::Bind { name, id_interface, id_version, id } => {
+                self.handle_bind(client, object_id, name, &id_interface, id_version, id)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,109 @@
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use wide::u32x8;
+
+use crate::protocols::generated::wl_shm::{self, format, Request};
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::global_registry::Global;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::{Interface, ObjectId};
 use crate::protocols::wl_shm_pool::WlShmPool; // Import the concrete WlShmPool
 
+/// The formats advertised to clients and accepted by create_buffer.
+/// ARGB8888 and XRGB8888 are required by the protocol.
+pub const SUPPORTED_FORMATS: &[u32] = &[
+    format::ARGB8888,
+    format::XRGB8888,
+    format::ABGR8888,
+    format::XBGR8888,
+    format::RGB565,
+    format::RGB888,
+];
+
+/// Bytes per pixel of a supported format.
+pub fn bytes_per_pixel(format: u32) -> Option<u32> {
+    match format {
+        format::ARGB8888 | format::XRGB8888 | format::ABGR8888 | format::XBGR8888 => Some(4),
+        format::RGB888 => Some(3),
+        format::RGB565 => Some(2),
+        _ => None,
+    }
+}
+
+/// Converts a row of pixels in a supported format to the texture format,
+/// RGBA8 in byte order. Formats without alpha get alpha 255.
+///
+/// The 32-bit formats are converted eight pixels at a time. This stays on
+/// the calling thread: a truncated pool is only caught on the thread that
+/// is reading it.
+pub fn convert_row_to_rgba(format: u32, src: &[u8], dst: &mut [u8]) {
+    // As little-endian words, ARGB8888 is 0xAARRGGBB and RGBA8 is 0xAABBGGRR.
+    let convert: fn(u32x8) -> u32x8 = match format {
+        format::ARGB8888 => swap_red_blue,
+        format::XRGB8888 => |p| swap_red_blue(p) | u32x8::splat(0xff000000),
+        format::XBGR8888 => |p| p | u32x8::splat(0xff000000),
+        _ => return convert_row_to_rgba_scalar(format, src, dst),
+    };
+    let mut src_chunks = src.chunks_exact(32);
+    let mut dst_chunks = dst.chunks_exact_mut(32);
+    for (src, dst) in (&mut src_chunks).zip(&mut dst_chunks) {
+        let mut words = [0u32; 8];
+        for (word, bytes) in words.iter_mut().zip(src.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        for (bytes, word) in dst.chunks_exact_mut(4).zip(convert(u32x8::new(words)).to_array()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+    convert_row_to_rgba_scalar(format, src_chunks.remainder(), dst_chunks.into_remainder());
+}
+
+fn swap_red_blue(p: u32x8) -> u32x8 {
+    (p & u32x8::splat(0xff00ff00)) | ((p >> 16_u32) & u32x8::splat(0xff)) | ((p & u32x8::splat(0xff)) << 16_u32)
+}
+
+/// The reference for `convert_row_to_rgba`, one pixel at a time.
+///
+/// wl_shm formats are little-endian, so ARGB8888 is B, G, R, A in memory.
+pub fn convert_row_to_rgba_scalar(format: u32, src: &[u8], dst: &mut [u8]) {
+    let pixels = dst.chunks_exact_mut(4);
+    match format {
+        format::ARGB8888 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(4)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+            }
+        }
+        format::XRGB8888 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(4)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0], 255]);
+            }
+        }
+        format::ABGR8888 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(4)) {
+                dst.copy_from_slice(src);
+            }
+        }
+        format::XBGR8888 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(4)) {
+                dst.copy_from_slice(&[src[0], src[1], src[2], 255]);
+            }
+        }
+        format::RGB888 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(3)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0], 255]);
+            }
+        }
+        format::RGB565 => {
+            for (dst, src) in pixels.zip(src.chunks_exact(2)) {
+                let pixel = u16::from_le_bytes([src[0], src[1]]);
+                let (r, g, b) = ((pixel >> 11) as u8, ((pixel >> 5) & 0x3f) as u8, (pixel & 0x1f) as u8);
+                // Scale to 8 bits, so full intensity stays 255.
+                dst.copy_from_slice(&[(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2), 255]);
+            }
+        }
+        _ => unreachable!("unsupported wl_shm format 0x{:x}", format),
+    }
+}
+
 /// Implements the wl_shm Wayland global.
 pub struct WlShm;
 
@@ -10,33 +112,91 @@ impl WlShm {
         Self
     }
 
-    pub fn on_bind(&self, _client: &mut WaylandClient, _object_id: u32) {
-        println!("[wl_shm] Client bound, advertising supported format ARGB8888");
-        // client.send_event(object_id, WlShm::Events::Format { format: 1 });
+    pub fn on_bind(&self, client: &mut WaylandClient, object_id: u32) {
+        eprintln!("[wl_shm] Client bound, advertising supported formats");
+        for &format in SUPPORTED_FORMATS {
+            wl_shm::send_format(client, object_id, format);
+        }
     }
 
-    fn handle_create_pool(&self, client: &mut WaylandClient, new_pool_id: u32, fd: i32, size: i32) {
-        println!("[wl_shm] Handling create_pool, creating pool {} with fd {} and size {}", new_pool_id, fd, size);
-        let pool = WlShmPool::new(client, new_pool_id, fd, size);
+    fn handle_create_pool(&self, client: &mut WaylandClient, self_id: u32, new_pool_id: u32, fd: OwnedFd, size: i32) -> Result<(), ProtocolError> {
+        eprintln!("[wl_shm] Handling create_pool, creating pool {} with fd {} and size {}", new_pool_id, fd.as_raw_fd(), size);
+        if size <= 0 {
+            return Err(ProtocolError::new(
+                self_id,
+                wl_shm::error::INVALID_STRIDE,
+                format!("invalid size ({})", size),
+            ));
+        }
+        let pool = WlShmPool::new(self_id, new_pool_id, fd, size)?;
         client.add_object(new_pool_id, Box::new(pool));
+        Ok(())
     }
 }
 
-impl WaylandObject for WlShm {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const CREATE_POOL: u16 = 0;
+impl RequestHandler for WlShm {
+    type Request = Request;
 
-        match request.opcode {
-            CREATE_POOL => {
-                let new_id = request.args.get(0).cloned().unwrap_or(0);
-                let size = request.args.get(1).cloned().unwrap_or(0) as i32;
-                let fd = 0; // Placeholder for the claimed file descriptor.
-
-                self.handle_create_pool(client, new_id, fd, size);
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::CreatePool { id, fd, size } => {
+                self.handle_create_pool(client, object_id, id, fd, size)?;
             }
-            _ => {
-                eprintln!("[wl_shm] Received unknown opcode: {}", request.opcode);
+            Request::Release => {
+                eprintln!("[wl_shm] release");
             }
         }
+        Ok(())
+    }
+}
+
+/// Advertises wl_shm.
+pub struct WlShmGlobal;
+
+impl Global for WlShmGlobal {
+    fn interface(&self) -> &'static Interface {
+        &wl_shm::INTERFACE
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+        let shm = WlShm::new();
+        shm.on_bind(client, id);
+        client.add_object(id, Box::new(shm));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_scalar_conversion() {
+        // Long enough for the eight pixel chunks and a tail.
+        let pixels = 8 * 3 + 5;
+        for &format in SUPPORTED_FORMATS {
+            let bytes_per_pixel = bytes_per_pixel(format).unwrap() as usize;
+            let src: Vec<u8> = (0..pixels * bytes_per_pixel).map(|i| (i * 37 + 11) as u8).collect();
+            let mut expected = vec![0; pixels * 4];
+            convert_row_to_rgba_scalar(format, &src, &mut expected);
+            let mut actual = vec![0; pixels * 4];
+            convert_row_to_rgba(format, &src, &mut actual);
+            assert_eq!(actual, expected, "format 0x{:x}", format);
+        }
+    }
+
+    #[test]
+    fn converts_to_rgba_byte_order() {
+        let mut rgba = [0; 4];
+        convert_row_to_rgba_scalar(format::ARGB8888, &[1, 2, 3, 4], &mut rgba);
+        assert_eq!(rgba, [3, 2, 1, 4]);
+        convert_row_to_rgba_scalar(format::XRGB8888, &[1, 2, 3, 4], &mut rgba);
+        assert_eq!(rgba, [3, 2, 1, 255]);
+        // Pure red in RGB565.
+        convert_row_to_rgba_scalar(format::RGB565, &0xf800u16.to_le_bytes(), &mut rgba);
+        assert_eq!(rgba, [255, 0, 0, 255]);
     }
 }
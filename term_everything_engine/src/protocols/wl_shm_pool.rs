@@ -1,42 +1,41 @@
-use std::collections::HashMap;
-use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
-use crate::protocols::wl_buffer::WlBuffer; // Import the concrete WlBuffer
-
-type WlBufferId = u32;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, PartialEq)]
-enum MapState {
-    Mmapped,
-    Destroyed,
-    DestroyWhenBuffersEmpty,
-}
-
-struct BufferInfo {
-    // This could hold more info if needed, but for now it's a marker.
-}
+use crate::protocols::generated::{wl_display, wl_shm, wl_shm_pool::Request};
+use crate::protocols::wl_buffer::WlBuffer; // Import the concrete WlBuffer
+use crate::protocols::wl_shm::bytes_per_pixel;
+use crate::shm_pool_memory::ShmPoolMemory;
+use crate::wayland_client::{WaylandClient, WL_DISPLAY_ID};
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
 /// Implements the wl_shm_pool object.
+/// The mapping is shared with the buffers created from the pool, so it
+/// stays alive until the pool and all its buffers are destroyed.
 pub struct WlShmPool {
     pool_id: u32,
-    client_state_ref: u32,
-    map_state: MapState,
-    buffers: HashMap<WlBufferId, BufferInfo>,
+    memory: Arc<Mutex<ShmPoolMemory>>,
 }
 
 impl WlShmPool {
-    pub fn new(client: &mut WaylandClient, pool_id: u32, fd: i32, size: i32) -> Self {
-        println!("[wl_shm_pool] new: mmapping fd {} for pool {}", fd, pool_id);
-        let success = true; // Assume success
-        let map_state = if success { MapState::Mmapped } else { MapState::Destroyed };
-        Self {
-            pool_id,
-            client_state_ref: client.get_client_state_ref(),
-            map_state,
-            buffers: HashMap::new(),
+    /// Maps the client's memory. The fd is closed once it is mapped.
+    /// A bad fd is an error of the wl_shm that was asked for the pool.
+    pub fn new(shm_id: u32, pool_id: u32, fd: OwnedFd, size: i32) -> Result<Self, ProtocolError> {
+        eprintln!("[wl_shm_pool] new: mmapping fd {} for pool {}", fd.as_raw_fd(), pool_id);
+        match ShmPoolMemory::new(&fd, size as usize) {
+            Ok(memory) => Ok(Self {
+                pool_id,
+                memory: Arc::new(Mutex::new(memory)),
+            }),
+            Err(e) => Err(ProtocolError::new(
+                shm_id,
+                wl_shm::error::INVALID_FD,
+                format!("failed mmap fd {}: {}", fd.as_raw_fd(), e),
+            )),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_create_buffer(
         &mut self,
         client: &mut WaylandClient,
@@ -46,77 +45,83 @@ impl WlShmPool {
         height: i32,
         stride: i32,
         format: u32
-    ) {
-        println!("[wl_shm_pool] create_buffer: creating buffer {}", new_buffer_id);
-        let buffer = WlBuffer::new(self.pool_id, offset, width, height, stride, format);
-        client.add_object(new_buffer_id, Box::new(buffer));
-        self.buffers.insert(new_buffer_id, BufferInfo{});
-    }
+    ) -> Result<(), ProtocolError> {
+        eprintln!("[wl_shm_pool] create_buffer: creating buffer {}", new_buffer_id);
 
-    fn handle_resize(&mut self, new_size: i32) {
-         if self.map_state != MapState::Destroyed {
-            println!("[wl_shm_pool] resize: resizing pool {} to {} bytes", self.pool_id, new_size);
-         }
-    }
+        let Some(bytes_per_pixel) = bytes_per_pixel(format) else {
+            return Err(ProtocolError::new(
+                self.pool_id,
+                wl_shm::error::INVALID_FORMAT,
+                format!("invalid format 0x{:x}", format),
+            ));
+        };
 
-    fn handle_destroy(&mut self, client: &mut WaylandClient) {
-        if self.buffers.is_empty() {
-            self.unmap(client);
-        } else {
-            println!("[wl_shm_pool] destroy: has active buffers, will destroy when empty.");
-            self.map_state = MapState::DestroyWhenBuffersEmpty;
+        // The same checks as libwayland's shm_pool_create_buffer.
+        let pool_size = self.memory.lock().unwrap().size() as i64;
+        let (offset, width, height, stride) = (offset as i64, width as i64, height as i64, stride as i64);
+        if offset < 0
+            || width <= 0
+            || height <= 0
+            || stride < width * bytes_per_pixel as i64
+            || offset + stride * height > pool_size
+        {
+            return Err(ProtocolError::new(
+                self.pool_id,
+                wl_shm::error::INVALID_STRIDE,
+                format!(
+                    "invalid width, height or stride ({}x{}, {}) at offset {} in a pool of {} bytes",
+                    width, height, stride, offset, pool_size
+                ),
+            ));
         }
-    }
 
-    pub fn buffer_destroyed(&mut self, client: &mut WaylandClient, buffer_id: WlBufferId) {
-        self.buffers.remove(&buffer_id);
-        if self.map_state == MapState::DestroyWhenBuffersEmpty && self.buffers.is_empty() {
-             self.unmap(client);
-        }
+        let buffer = WlBuffer::new(
+            Arc::clone(&self.memory),
+            offset as usize,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+        );
+        client.add_object(new_buffer_id, Box::new(buffer));
+        Ok(())
     }
 
-    fn unmap(&mut self, _client: &mut WaylandClient) {
-        if self.map_state != MapState::Destroyed {
-            println!("[wl_shm_pool] unmap: unmapping and destroying pool {}", self.pool_id);
-            self.map_state = MapState::Destroyed;
+    fn handle_resize(&mut self, new_size: i32) -> Result<(), ProtocolError> {
+        eprintln!("[wl_shm_pool] resize: resizing pool {} to {} bytes", self.pool_id, new_size);
+        let mut memory = self.memory.lock().unwrap();
+        // Pools can only grow, buffers may point anywhere in them.
+        if new_size < 0 || (new_size as usize) < memory.size() {
+            return Err(ProtocolError::new(
+                self.pool_id,
+                wl_shm::error::INVALID_STRIDE,
+                format!("shrinking pool {} from {} to {} bytes", self.pool_id, memory.size(), new_size),
+            ));
         }
+        memory.grow(new_size as usize).map_err(|e| {
+            ProtocolError::new(
+                WL_DISPLAY_ID,
+                wl_display::error::NO_MEMORY,
+                format!("failed to grow pool {} to {} bytes: {}", self.pool_id, new_size, e),
+            )
+        })
     }
 }
 
-impl WaylandObject for WlShmPool {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const CREATE_BUFFER: u16 = 0;
-        const DESTROY: u16 = 1;
-        const RESIZE: u16 = 2;
+impl RequestHandler for WlShmPool {
+    type Request = Request;
 
-        match request.opcode {
-            CREATE_BUFFER => {
-                let new_id = request.args.get(0).cloned().unwrap_or(0);
-                let offset = request.args.get(1).cloned().unwrap_or(0) as i32;
-                let width = request.args.get(2).cloned().unwrap_or(0) as i32;
-                let height = request.args.get(3).cloned().unwrap_or(0) as i32;
-                let stride = request.args.get(4).cloned().unwrap_or(0) as i32;
-                let format = request.args.get(5).cloned().unwrap_or(0);
-                self.handle_create_buffer(client, new_id, offset, width, height, stride, format);
-            }
-            RESIZE => {
-                let new_size = request.args.get(0).cloned().unwrap_or(0) as i32;
-                self.handle_resize(new_size);
-            }
-            DESTROY => {
-                self.handle_destroy(client);
+    fn handle_request(&mut self, client: &mut WaylandClient, _object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::CreateBuffer { id, offset, width, height, stride, format } => {
+                self.handle_create_buffer(client, id, offset, width, height, stride, format)?;
             }
-            _ => {
-                eprintln!("[wl_shm_pool] Received unknown opcode: {}", request.opcode);
+            Request::Resize { size } => {
+                self.handle_resize(size)?;
             }
+            // The mapping is released once the last buffer is gone.
+            Request::Destroy => {}
         }
-    }
-}
-
-impl Drop for WlShmPool {
-    fn drop(&mut self) {
-        if self.map_state != MapState::Destroyed {
-             println!("[wl_shm_pool] Drop: unmapping pool {}", self.pool_id);
-        }
+        Ok(())
     }
 }
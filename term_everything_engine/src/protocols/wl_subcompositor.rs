@@ -0,0 +1,112 @@
+use crate::global_registry::Global;
+use crate::protocols::generated::wl_subcompositor::{self, error, Request};
+use crate::protocols::wl_subsurface::WlSubsurface;
+use crate::protocols::wl_surface::{SurfaceRole, WlSurface};
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::{Interface, ObjectId};
+
+/// Implements the wl_subcompositor Wayland global.
+/// It turns surfaces into subsurfaces of other surfaces.
+pub struct WlSubcompositor;
+
+impl WlSubcompositor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn handle_get_subsurface(
+        &self,
+        client: &mut WaylandClient,
+        self_id: u32,
+        new_subsurface_id: u32,
+        surface_id: u32,
+        parent_id: u32,
+    ) -> Result<(), ProtocolError> {
+        eprintln!("[wl_subcompositor] get_subsurface: surface {} of parent {}", surface_id, parent_id);
+
+        if client.get_object::<WlSurface>(surface_id).is_none() {
+            return Err(ProtocolError::new(
+                self_id,
+                error::BAD_SURFACE,
+                format!("{} is not a wl_surface", surface_id),
+            ));
+        }
+        if surface_id == parent_id {
+            return Err(ProtocolError::new(
+                self_id,
+                error::BAD_SURFACE,
+                format!("wl_surface {} cannot become a subsurface of itself", surface_id),
+            ));
+        }
+        // The parent must not be a descendant of the surface.
+        let mut ancestor = Some(parent_id);
+        while let Some(id) = ancestor {
+            let Some(surface) = client.get_object::<WlSurface>(id) else {
+                return Err(ProtocolError::new(
+                    self_id,
+                    error::BAD_PARENT,
+                    format!("parent {} is not a wl_surface", id),
+                ));
+            };
+            if id == surface_id {
+                return Err(ProtocolError::new(
+                    self_id,
+                    error::BAD_PARENT,
+                    format!("wl_surface {} is an ancestor of its parent {}", surface_id, parent_id),
+                ));
+            }
+            ancestor = surface
+                .subsurface_id()
+                .and_then(|subsurface_id| client.get_object::<WlSubsurface>(subsurface_id))
+                .map(|subsurface| subsurface.parent_id());
+        }
+
+        // Another role, or a wl_subsurface that is still around.
+        if !client.set_surface_role(surface_id, SurfaceRole::Subsurface, Some(new_subsurface_id)) {
+            return Err(ProtocolError::new(
+                self_id,
+                error::BAD_SURFACE,
+                format!("wl_surface {} already has a role", surface_id),
+            ));
+        }
+        if let Some(parent) = client.get_object_mut::<WlSurface>(parent_id) {
+            parent.add_subsurface(surface_id);
+        }
+        client.add_object(new_subsurface_id, Box::new(WlSubsurface::new(surface_id, parent_id)));
+        Ok(())
+    }
+}
+
+impl RequestHandler for WlSubcompositor {
+    type Request = Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::GetSubsurface { id, surface, parent } => {
+                self.handle_get_subsurface(client, object_id, id, surface, parent)?;
+            }
+            Request::Destroy => {
+                eprintln!("[wl_subcompositor] destroy");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Advertises wl_subcompositor.
+pub struct WlSubcompositorGlobal;
+
+impl Global for WlSubcompositorGlobal {
+    fn interface(&self) -> &'static Interface {
+        &wl_subcompositor::INTERFACE
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+        client.add_object(id, Box::new(WlSubcompositor::new()));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::protocols::generated::wl_subsurface::{error, Request};
+use crate::protocols::wl_surface::{self, WlSurface};
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+
+/// Implements the wl_subsurface role object.
+/// Its position and stacking live in the parent surface, which applies
+/// them on its next commit.
+pub struct WlSubsurface {
+    surface_id: u32,
+    parent_id: u32,
+    // Subsurfaces start out synchronized.
+    sync: bool,
+}
+
+/// The wl_subsurface of a surface, if it is one.
+fn subsurface_of(client: &WaylandClient, surface_id: u32) -> Option<u32> {
+    client.get_object::<WlSurface>(surface_id)?.subsurface_id()
+}
+
+/// Whether commits to the subsurface are cached until its parent's state is
+/// applied: it is synchronized itself, or one of its ancestors is.
+pub fn is_synchronized(client: &WaylandClient, subsurface_id: u32) -> bool {
+    let mut next = Some(subsurface_id);
+    while let Some(subsurface_id) = next {
+        let Some(subsurface) = client.get_object::<WlSubsurface>(subsurface_id) else {
+            return false;
+        };
+        if subsurface.sync {
+            return true;
+        }
+        next = subsurface_of(client, subsurface.parent_id);
+    }
+    false
+}
+
+impl WlSubsurface {
+    pub fn new(surface_id: u32, parent_id: u32) -> Self {
+        Self { surface_id, parent_id, sync: true }
+    }
+
+    pub fn parent_id(&self) -> u32 {
+        self.parent_id
+    }
+
+    fn handle_place(&self, client: &mut WaylandClient, self_id: u32, sibling: u32, above: bool) -> Result<(), ProtocolError> {
+        let surface_id = self.surface_id;
+        let placed = client
+            .get_object_mut::<WlSurface>(self.parent_id)
+            .is_some_and(|parent| parent.place_subsurface(surface_id, sibling, above));
+        if !placed {
+            return Err(ProtocolError::new(
+                self_id,
+                error::BAD_SURFACE,
+                format!("wl_surface {} is not a sibling or the parent", sibling),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RequestHandler for WlSubsurface {
+    type Request = Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::SetPosition { x, y } => {
+                if let Some(parent) = client.get_object_mut::<WlSurface>(self.parent_id) {
+                    parent.set_subsurface_position(self.surface_id, x, y);
+                }
+            }
+            Request::PlaceAbove { sibling } => {
+                self.handle_place(client, object_id, sibling, true)?;
+            }
+            Request::PlaceBelow { sibling } => {
+                self.handle_place(client, object_id, sibling, false)?;
+            }
+            Request::SetSync => self.sync = true,
+            Request::SetDesync => {
+                self.sync = false;
+                // What was cached shows up now, unless the parent is still synchronized.
+                if !subsurface_of(client, self.parent_id).is_some_and(|parent| is_synchronized(client, parent)) {
+                    wl_surface::apply_cached_states(client, vec![self.surface_id]);
+                }
+            }
+            Request::Destroy => {
+                eprintln!("[wl_subsurface] destroy");
+            }
+        }
+        Ok(())
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, _object_id: ObjectId) {
+        if let Some(parent) = client.get_object_mut::<WlSurface>(self.parent_id) {
+            parent.remove_subsurface(self.surface_id);
+        }
+        client.clear_surface_role_object(self.surface_id);
+    }
+}
@@ -1,119 +1,446 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::protocols::generated::{wl_buffer, wl_surface::Request};
+use crate::protocols::wl_buffer::WlBuffer;
+use crate::protocols::wl_callback::WlCallback;
+use crate::protocols::wl_subsurface;
+use crate::region::{Rect, Region};
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
 type WlBufferId = u32;
 type WlCallbackId = u32;
-type WlRegionId = u32;
 type RoleObjectId = u32;
 
 // --- State Management Structs ---
 
 /// The role of a surface determines its behavior (e.g., toplevel, popup).
-#[derive(Debug, Clone, Copy)]
+/// A surface keeps its role for good; only the role object comes and goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SurfaceRole {
-    XdgToplevel { toplevel_id: RoleObjectId },
-    XdgPopup { popup_id: RoleObjectId },
-    // Other roles like cursor, sub-surface would go here.
+    XdgToplevel,
+    XdgPopup,
+    Subsurface,
+    Cursor,
+}
+
+impl SurfaceRole {
+    /// The name used in protocol error messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            SurfaceRole::XdgToplevel => "xdg_toplevel",
+            SurfaceRole::XdgPopup => "xdg_popup",
+            SurfaceRole::Subsurface => "sub_surface",
+            SurfaceRole::Cursor => "cursor",
+        }
+    }
+}
+
+/// An entry in the stack of a surface and its subsurfaces. The surface
+/// itself is in there too, at (0, 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEntry {
+    pub surface_id: u32,
+    // Relative to the parent surface.
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Orders surfaces by when they were mapped, so newer windows are drawn on top.
+static NEXT_MAP_ORDER: AtomicU64 = AtomicU64::new(0);
+
+/// A copy of the last committed buffer, so the client can reuse its buffer
+/// right away and never changes what is being composited.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8, `width * 4` bytes per row.
+    pub pixels: Vec<u8>,
 }
 
 #[derive(Default, Debug)]
 struct WlSurfacePendingState {
+    // Whether attach was called; `buffer` may be None to detach.
+    newly_attached: bool,
     buffer: Option<WlBufferId>,
-    damage: Vec<(i32, i32, i32, i32)>,
+    // In buffer coordinates, from both damage and damage_buffer.
+    damage: Region,
     offset: (i32, i32),
+    frame_callbacks: Vec<WlCallbackId>,
 }
 
 #[derive(Debug, Default)]
 struct WlSurfaceCurrentState {
-    buffer: Option<WlBufferId>,
-    texture: Option<Vec<u8>>,
+    texture: Option<Texture>,
+    // What changed in the texture since the compositor last looked.
+    damage: Region,
     offset: (i32, i32),
+    // Fired by the render loop once the next frame has been drawn.
+    frame_callbacks: Vec<WlCallbackId>,
+}
+
+/// What a synchronized subsurface committed, waiting for its parent's state
+/// to be applied. The buffer is already copied, so it is released on commit
+/// like any other.
+#[derive(Debug, Default)]
+struct WlSurfaceCachedState {
+    // Set if a commit attached a buffer; `Some(None)` if it detached it.
+    texture: Option<Option<Texture>>,
+    damage: Region,
+    offset: Option<(i32, i32)>,
+    stack: Option<Vec<StackEntry>>,
+    frame_callbacks: Vec<WlCallbackId>,
 }
 
 /// Implements the wl_surface Wayland object.
 pub struct WlSurface {
+    id: u32,
     pending: WlSurfacePendingState,
     current: WlSurfaceCurrentState,
+    cached: Option<WlSurfaceCachedState>,
     role: Option<SurfaceRole>,
+    // The xdg_toplevel, xdg_popup or wl_subsurface giving the surface its role, while it exists.
+    role_object_id: Option<RoleObjectId>,
+    // The subsurface stack, bottom to top. Changes are applied on commit,
+    // but unlike the rest of the pending state, they carry over.
+    pending_stack: Vec<StackEntry>,
+    stack: Vec<StackEntry>,
+    // Set while the surface has a texture.
+    map_order: Option<u64>,
 }
 
 impl WlSurface {
-    pub fn new() -> Self {
+    pub fn new(id: u32) -> Self {
+        let stack = vec![StackEntry { surface_id: id, x: 0, y: 0 }];
         Self {
+            id,
             pending: WlSurfacePendingState::default(),
             current: WlSurfaceCurrentState::default(),
+            cached: None,
             role: None,
+            role_object_id: None,
+            pending_stack: stack.clone(),
+            stack,
+            map_order: None,
         }
     }
 
     // --- Role Management ---
-    pub fn set_role(&mut self, role_name: &'static str, role_object_id: RoleObjectId) {
-        let new_role = match role_name {
-            "xdg_toplevel" => Some(SurfaceRole::XdgToplevel{ toplevel_id: role_object_id }),
-            "xdg_popup" => Some(SurfaceRole::XdgPopup{ popup_id: role_object_id }),
+    /// Returns false if the surface already has a different role, or its
+    /// role object still exists. Giving a surface the role it already has is
+    /// allowed, e.g. reusing a cursor surface, which has no role object.
+    /// Which protocol error that is depends on the request assigning the role.
+    pub fn set_role(&mut self, role: SurfaceRole, role_object_id: Option<RoleObjectId>) -> bool {
+        if self.role.is_some_and(|current| current != role) || self.role_object_id.is_some() {
+            return false;
+        }
+        self.role = Some(role);
+        self.role_object_id = role_object_id;
+        true
+    }
+
+    /// The role object was destroyed. The surface keeps its role and can
+    /// only be given a new object of the same role.
+    pub fn clear_role_object(&mut self) {
+        self.role_object_id = None;
+    }
+
+    pub fn role(&self) -> Option<SurfaceRole> {
+        self.role
+    }
+
+    pub fn role_object_id(&self) -> Option<RoleObjectId> {
+        self.role_object_id
+    }
+
+    /// The wl_subsurface of the surface, if it is one.
+    pub fn subsurface_id(&self) -> Option<RoleObjectId> {
+        match self.role {
+            Some(SurfaceRole::Subsurface) => self.role_object_id,
             _ => None,
-        };
-        if self.role.is_none() {
-            self.role = new_role;
-        } else {
-            // This should be a protocol error.
-            eprintln!("Error: Attempted to assign a new role to a surface that already has one.");
         }
     }
 
-    pub fn get_role_name(&self) -> Option<String> {
-        self.role.map(|r| match r {
-            SurfaceRole::XdgToplevel{..} => "xdg_toplevel".to_string(),
-            SurfaceRole::XdgPopup{..} => "xdg_popup".to_string(),
-        })
+    // --- Subsurfaces ---
+    /// A new subsurface goes on top of the stack right away.
+    pub fn add_subsurface(&mut self, surface_id: u32) {
+        let entry = StackEntry { surface_id, x: 0, y: 0 };
+        self.pending_stack.push(entry);
+        self.stack.push(entry);
+    }
+
+    pub fn remove_subsurface(&mut self, surface_id: u32) {
+        self.pending_stack.retain(|entry| entry.surface_id != surface_id);
+        self.stack.retain(|entry| entry.surface_id != surface_id);
+    }
+
+    pub fn set_subsurface_position(&mut self, surface_id: u32, x: i32, y: i32) {
+        if let Some(entry) = self.pending_stack.iter_mut().find(|entry| entry.surface_id == surface_id) {
+            entry.x = x;
+            entry.y = y;
+        }
+    }
+
+    /// Moves a subsurface right above or below a sibling or this surface.
+    /// Returns false if `sibling` is neither.
+    pub fn place_subsurface(&mut self, surface_id: u32, sibling: u32, above: bool) -> bool {
+        if surface_id == sibling || !self.pending_stack.iter().any(|entry| entry.surface_id == sibling) {
+            return false;
+        }
+        let Some(index) = self.pending_stack.iter().position(|entry| entry.surface_id == surface_id) else {
+            return false;
+        };
+        let entry = self.pending_stack.remove(index);
+        let sibling_index = self.pending_stack.iter().position(|entry| entry.surface_id == sibling).unwrap();
+        self.pending_stack.insert(if above { sibling_index + 1 } else { sibling_index }, entry);
+        true
+    }
+
+    /// This surface and its subsurfaces, bottom to top.
+    pub fn stack(&self) -> &[StackEntry] {
+        &self.stack
     }
 
     // --- Request Handler Methods ---
-    fn handle_attach(&mut self, buffer_id: WlBufferId, x: i32, y: i32) {
-        self.pending.buffer = Some(buffer_id);
+    fn handle_attach(&mut self, buffer_id: Option<WlBufferId>, x: i32, y: i32) {
+        self.pending.newly_attached = true;
+        self.pending.buffer = buffer_id;
         self.pending.offset = (x, y);
     }
     fn handle_damage(&mut self, x: i32, y: i32, width: i32, height: i32) {
-        self.pending.damage.push((x, y, width, height));
+        // Surface and buffer coordinates are the same: buffer scale and
+        // transform are not supported, so they are always 1 and normal.
+        self.pending.damage.add(Rect::new(x, y, width, height));
     }
-    fn handle_frame(&self, _client: &mut WaylandClient, _callback_id: WlCallbackId) {}
-    fn handle_commit(&mut self) {
-        self.current.buffer = self.pending.buffer;
-        self.current.offset = self.pending.offset;
-        self.pending = WlSurfacePendingState::default();
+    fn handle_damage_buffer(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        self.pending.damage.add(Rect::new(x, y, width, height));
     }
-}
-
-impl WaylandObject for WlSurface {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const DESTROY: u16 = 0;
-        const ATTACH: u16 = 1;
-        const DAMAGE: u16 = 2;
-        const FRAME: u16 = 3;
-        const COMMIT: u16 = 6;
-
-        match request.opcode {
-            ATTACH => {
-                let buffer_id = request.args.get(0).cloned().unwrap_or(0);
-                let x = request.args.get(1).cloned().unwrap_or(0) as i32;
-                let y = request.args.get(2).cloned().unwrap_or(0) as i32;
-                self.handle_attach(buffer_id, x, y);
+    fn handle_frame(&mut self, client: &mut WaylandClient, callback_id: WlCallbackId) {
+        client.add_object(callback_id, Box::new(WlCallback::new()));
+        self.pending.frame_callbacks.push(callback_id);
+    }
+    fn handle_commit(&mut self, client: &mut WaylandClient) -> Result<(), ProtocolError> {
+        let pending = std::mem::take(&mut self.pending);
+        let synchronized = self
+            .subsurface_id()
+            .is_some_and(|subsurface_id| wl_subsurface::is_synchronized(client, subsurface_id));
+        if synchronized {
+            return self.cache(client, pending);
+        }
+        // A desynchronized subsurface applies what it cached before along with the new state.
+        if let Some(cached) = self.cached.take() {
+            if self.apply_cached(cached) {
+                client.request_redraw();
             }
-            DAMAGE => {
-                let x = request.args.get(0).cloned().unwrap_or(0) as i32;
-                let y = request.args.get(1).cloned().unwrap_or(0) as i32;
-                let width = request.args.get(2).cloned().unwrap_or(0) as i32;
-                let height = request.args.get(3).cloned().unwrap_or(0) as i32;
-                self.handle_damage(x, y, width, height);
+        }
+        if pending.newly_attached {
+            self.current.offset = pending.offset;
+            match pending.buffer {
+                Some(buffer_id) => copy_buffer(client, buffer_id, pending.damage, &mut self.current.texture, &mut self.current.damage)?,
+                None => remove_texture(&mut self.current.texture, &mut self.current.damage),
             }
-            FRAME => {
-                if let Some(callback_id) = request.args.get(0) {
-                    self.handle_frame(client, *callback_id);
-                }
+            self.update_map_order();
+        }
+        if self.pending_stack != self.stack {
+            self.stack = self.pending_stack.clone();
+            client.request_redraw();
+        }
+        self.current.frame_callbacks.extend(pending.frame_callbacks);
+        apply_cached_states(client, self.subsurface_ids());
+        Ok(())
+    }
+
+    /// Adds a commit to the cached state. Later commits build on the texture
+    /// of earlier ones, so only the first copies the current texture.
+    fn cache(&mut self, client: &mut WaylandClient, pending: WlSurfacePendingState) -> Result<(), ProtocolError> {
+        let cached = self.cached.get_or_insert_with(WlSurfaceCachedState::default);
+        if pending.newly_attached {
+            cached.offset = Some(pending.offset);
+            let texture = cached.texture.get_or_insert_with(|| self.current.texture.clone());
+            match pending.buffer {
+                Some(buffer_id) => copy_buffer(client, buffer_id, pending.damage, texture, &mut cached.damage)?,
+                None => remove_texture(texture, &mut cached.damage),
             }
-            COMMIT => self.handle_commit(),
-            DESTROY => println!("[wl_surface] destroy called (stub)"),
-            _ => eprintln!("[wl_surface] Received unknown opcode: {}", request.opcode),
         }
+        cached.stack = Some(self.pending_stack.clone());
+        cached.frame_callbacks.extend(pending.frame_callbacks);
+        Ok(())
+    }
+
+    /// Makes the cached state current. Returns true if the subsurface stack
+    /// changed, which damage does not cover.
+    fn apply_cached(&mut self, cached: WlSurfaceCachedState) -> bool {
+        if let Some(texture) = cached.texture {
+            self.current.texture = texture;
+            self.update_map_order();
+        }
+        self.current.damage.union(&cached.damage);
+        if let Some(offset) = cached.offset {
+            self.current.offset = offset;
+        }
+        self.current.frame_callbacks.extend(cached.frame_callbacks);
+        match cached.stack {
+            Some(stack) if stack != self.stack => {
+                self.stack = stack;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update_map_order(&mut self) {
+        match (&self.current.texture, self.map_order) {
+            (Some(_), None) => self.map_order = Some(NEXT_MAP_ORDER.fetch_add(1, Ordering::Relaxed)),
+            (None, Some(_)) => self.map_order = None,
+            _ => {}
+        }
+    }
+
+    fn subsurface_ids(&self) -> Vec<u32> {
+        self.stack
+            .iter()
+            .map(|entry| entry.surface_id)
+            .filter(|&surface_id| surface_id != self.id)
+            .collect()
+    }
+
+    /// Takes what changed since the last call, in buffer coordinates.
+    pub fn take_damage(&mut self) -> Region {
+        std::mem::take(&mut self.current.damage)
+    }
+
+    pub fn texture(&self) -> Option<&Texture> {
+        self.current.texture.as_ref()
+    }
+
+    /// When the surface got its texture, to stack windows. `None` while unmapped.
+    pub fn map_order(&self) -> Option<u64> {
+        self.map_order
+    }
+
+    /// Takes the committed frame callbacks, for the render loop to fire
+    /// once it has drawn the frame they belong to.
+    pub fn take_frame_callbacks(&mut self) -> Vec<WlCallbackId> {
+        std::mem::take(&mut self.current.frame_callbacks)
+    }
+}
+
+/// Copies the damaged parts of the buffer into the texture and releases
+/// the buffer; the texture is all that is drawn from now on.
+fn copy_buffer(
+    client: &mut WaylandClient,
+    buffer_id: WlBufferId,
+    damage: Region,
+    texture: &mut Option<Texture>,
+    texture_damage: &mut Region,
+) -> Result<(), ProtocolError> {
+    let Some(buffer) = client.get_object::<WlBuffer>(buffer_id) else {
+        // Destroyed before the commit, there is nothing to show.
+        remove_texture(texture, texture_damage);
+        return Ok(());
+    };
+    let bounds = buffer.bounds();
+    match texture {
+        Some(texture) if texture.width == bounds.width as u32 && texture.height == bounds.height as u32 => {
+            let damage = damage.clip(&bounds);
+            buffer.copy_to_rgba(buffer_id, damage.rects(), &mut texture.pixels)?;
+            texture_damage.union(&damage);
+        }
+        // A new size means there is nothing to update; copy it all.
+        texture => {
+            *texture = Some(Texture {
+                width: bounds.width as u32,
+                height: bounds.height as u32,
+                pixels: buffer.to_rgba(buffer_id)?,
+            });
+            texture_damage.add(bounds);
+        }
+    }
+    wl_buffer::send_release(client, buffer_id);
+    Ok(())
+}
+
+fn remove_texture(texture: &mut Option<Texture>, texture_damage: &mut Region) {
+    if let Some(texture) = texture.take() {
+        texture_damage.add(Rect::new(0, 0, texture.width as i32, texture.height as i32));
+    }
+}
+
+/// Applies the cached state of the given surfaces, then of their own
+/// subsurfaces, as their parents' state has just been applied.
+/// A surface without cached state keeps the cache of its subsurfaces
+/// for its own next commit.
+pub fn apply_cached_states(client: &mut WaylandClient, mut surface_ids: Vec<u32>) {
+    while let Some(surface_id) = surface_ids.pop() {
+        let Some(surface) = client.get_object_mut::<WlSurface>(surface_id) else {
+            continue;
+        };
+        let Some(cached) = surface.cached.take() else {
+            continue;
+        };
+        let restacked = surface.apply_cached(cached);
+        surface_ids.extend(surface.subsurface_ids());
+        if restacked {
+            client.request_redraw();
+        }
+    }
+}
+
+impl RequestHandler for WlSurface {
+    type Request = Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, _object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::Attach { buffer, x, y } => self.handle_attach(buffer, x, y),
+            Request::Damage { x, y, width, height } => self.handle_damage(x, y, width, height),
+            Request::DamageBuffer { x, y, width, height } => self.handle_damage_buffer(x, y, width, height),
+            Request::Frame { callback } => self.handle_frame(client, callback),
+            Request::Commit => self.handle_commit(client)?,
+            Request::Destroy => eprintln!("[wl_surface] destroy"),
+            request => eprintln!("[wl_surface] Unhandled request: {:?}", request),
+        }
+        Ok(())
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, _object_id: ObjectId) {
+        if self.current.texture.is_some() {
+            client.request_redraw();
+        }
+        // Callbacks of a destroyed surface never fire.
+        let cached_callbacks = self.cached.take().map(|cached| cached.frame_callbacks).unwrap_or_default();
+        for callback_id in self.pending.frame_callbacks.drain(..).chain(self.current.frame_callbacks.drain(..)).chain(cached_callbacks) {
+            client.destroy_object(callback_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_its_first_role() {
+        let mut surface = WlSurface::new(3);
+        assert!(surface.set_role(SurfaceRole::Cursor, None));
+        // Reusing a cursor surface is fine.
+        assert!(surface.set_role(SurfaceRole::Cursor, None));
+        assert!(!surface.set_role(SurfaceRole::Subsurface, Some(4)));
+        assert_eq!(surface.role(), Some(SurfaceRole::Cursor));
+    }
+
+    #[test]
+    fn keeps_its_role_after_the_role_object_is_gone() {
+        let mut surface = WlSurface::new(3);
+        assert!(surface.set_role(SurfaceRole::Subsurface, Some(4)));
+        assert!(!surface.set_role(SurfaceRole::Subsurface, Some(5)));
+        assert_eq!(surface.subsurface_id(), Some(4));
+
+        surface.clear_role_object();
+        assert_eq!(surface.subsurface_id(), None);
+        assert!(!surface.set_role(SurfaceRole::XdgToplevel, Some(5)));
+        assert!(surface.set_role(SurfaceRole::Subsurface, Some(5)));
+        assert_eq!(surface.subsurface_id(), Some(5));
     }
 }
@@ -0,0 +1,110 @@
+use crate::protocols::generated::{xdg_popup::{self, Request}, xdg_wm_base};
+use crate::protocols::xdg_positioner::XdgPositioner;
+use crate::protocols::xdg_surface;
+use crate::region::Rect;
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+
+/// Implements the xdg_popup role object.
+pub struct XdgPopup {
+    // The xdg_wm_base the xdg_surface came from, which invalid_positioner is posted on.
+    wm_base_id: u32,
+    // The xdg_surface this role was created from; it sends the final configure.
+    xdg_surface_id: u32,
+    // The wl_surface that has the xdg_popup role.
+    surface_id: u32,
+    // The wl_surface of the parent xdg_surface. Popups without a parent
+    // have to be placed by another protocol, and are not drawn.
+    parent_surface_id: Option<u32>,
+    // Relative to the parent surface.
+    geometry: Rect,
+}
+
+/// The positioner's rules, or an invalid_positioner error on the
+/// xdg_wm_base `wm_base_id` if it is not usable.
+fn get_placement(client: &WaylandClient, wm_base_id: u32, positioner_id: u32) -> Result<Rect, ProtocolError> {
+    match client.get_object::<XdgPositioner>(positioner_id).map(|p| p.placement()) {
+        Some(placement) if placement.is_complete() => Ok(placement.geometry()),
+        _ => Err(ProtocolError::new(
+            wm_base_id,
+            xdg_wm_base::error::INVALID_POSITIONER,
+            format!("xdg_positioner {} is incomplete", positioner_id),
+        )),
+    }
+}
+
+impl XdgPopup {
+    pub fn new(
+        client: &WaylandClient,
+        wm_base_id: u32,
+        xdg_surface_id: u32,
+        surface_id: u32,
+        parent_surface_id: Option<u32>,
+        positioner_id: u32,
+    ) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            wm_base_id,
+            xdg_surface_id,
+            surface_id,
+            parent_surface_id,
+            geometry: get_placement(client, wm_base_id, positioner_id)?,
+        })
+    }
+
+    pub fn surface_id(&self) -> u32 {
+        self.surface_id
+    }
+
+    pub fn parent_surface_id(&self) -> Option<u32> {
+        self.parent_surface_id
+    }
+
+    /// The popup's position relative to its parent surface.
+    pub fn position(&self) -> (i32, i32) {
+        (self.geometry.x, self.geometry.y)
+    }
+
+    /// Sends xdg_popup.configure with the popup's geometry.
+    /// It only takes effect once followed by xdg_surface.configure.
+    pub fn send_configure(&self, client: &mut WaylandClient, self_id: u32) {
+        let g = self.geometry;
+        xdg_popup::send_configure(client, self_id, g.x, g.y, g.width, g.height);
+    }
+
+    /// Only reached at version 3 and up, like repositioned: both are
+    /// version-checked by the generated code.
+    fn handle_reposition(&mut self, client: &mut WaylandClient, self_id: u32, positioner_id: u32, token: u32) -> Result<(), ProtocolError> {
+        self.geometry = get_placement(client, self.wm_base_id, positioner_id)?;
+        xdg_popup::send_repositioned(client, self_id, token);
+        self.send_configure(client, self_id);
+        xdg_surface::send_configure(client, self.xdg_surface_id);
+        client.request_redraw();
+        Ok(())
+    }
+}
+
+impl RequestHandler for XdgPopup {
+    type Request = Request;
+
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::Reposition { positioner, token } => {
+                self.handle_reposition(client, object_id, positioner, token)?;
+            }
+            Request::Grab { .. } => {
+                // There is only one seat and nothing else to take input from.
+                eprintln!("[xdg_popup] grab");
+            }
+            Request::Destroy => {
+                eprintln!("[xdg_popup] destroy");
+            }
+        }
+        Ok(())
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, _object_id: ObjectId) {
+        // Role teardown: the surface is unmapped and can get a new role object.
+        client.clear_surface_role_object(self.surface_id);
+    }
+}
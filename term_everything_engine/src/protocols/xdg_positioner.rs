@@ -0,0 +1,120 @@
+use crate::protocols::generated::xdg_positioner::{anchor, error, gravity, Request};
+use crate::region::Rect;
+use crate::wayland_client::WaylandClient;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+
+/// The placement rules collected by an xdg_positioner.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub size: (i32, i32),
+    pub anchor_rect: Rect,
+    pub anchor: u32,
+    pub gravity: u32,
+    pub offset: (i32, i32),
+}
+
+impl Placement {
+    /// Both the size and the anchor rect have to be set before a positioner is used.
+    pub fn is_complete(&self) -> bool {
+        self.size.0 > 0 && self.size.1 > 0 && self.anchor_rect.width >= 0 && self.anchor_rect.height >= 0
+    }
+
+    /// The popup's rect relative to its parent surface.
+    /// The constraint adjustments are not applied: the virtual monitor
+    /// clips whatever does not fit.
+    pub fn geometry(&self) -> Rect {
+        let r = self.anchor_rect;
+        let anchor_x = match self.anchor {
+            anchor::LEFT | anchor::TOP_LEFT | anchor::BOTTOM_LEFT => r.x,
+            anchor::RIGHT | anchor::TOP_RIGHT | anchor::BOTTOM_RIGHT => r.x + r.width,
+            _ => r.x + r.width / 2,
+        };
+        let anchor_y = match self.anchor {
+            anchor::TOP | anchor::TOP_LEFT | anchor::TOP_RIGHT => r.y,
+            anchor::BOTTOM | anchor::BOTTOM_LEFT | anchor::BOTTOM_RIGHT => r.y + r.height,
+            _ => r.y + r.height / 2,
+        };
+
+        // The gravity is the direction the popup extends in from the anchor point.
+        let (width, height) = self.size;
+        let x = match self.gravity {
+            gravity::LEFT | gravity::TOP_LEFT | gravity::BOTTOM_LEFT => anchor_x - width,
+            gravity::RIGHT | gravity::TOP_RIGHT | gravity::BOTTOM_RIGHT => anchor_x,
+            _ => anchor_x - width / 2,
+        };
+        let y = match self.gravity {
+            gravity::TOP | gravity::TOP_LEFT | gravity::TOP_RIGHT => anchor_y - height,
+            gravity::BOTTOM | gravity::BOTTOM_LEFT | gravity::BOTTOM_RIGHT => anchor_y,
+            _ => anchor_y - height / 2,
+        };
+        Rect::new(x + self.offset.0, y + self.offset.1, width, height)
+    }
+}
+
+/// Implements the xdg_positioner object.
+pub struct XdgPositioner {
+    placement: Placement,
+}
+
+impl XdgPositioner {
+    pub fn new() -> Self {
+        Self {
+            placement: Placement {
+                size: (0, 0),
+                // Not set yet.
+                anchor_rect: Rect::new(0, 0, -1, -1),
+                anchor: anchor::NONE,
+                gravity: gravity::NONE,
+                offset: (0, 0),
+            },
+        }
+    }
+
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+}
+
+impl RequestHandler for XdgPositioner {
+    type Request = Request;
+
+    fn handle_request(&mut self, _client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::SetSize { width, height } => {
+                if width <= 0 || height <= 0 {
+                    return Err(ProtocolError::new(
+                        object_id,
+                        error::INVALID_INPUT,
+                        format!("invalid size {}x{}", width, height),
+                    ));
+                }
+                self.placement.size = (width, height);
+            }
+            Request::SetAnchorRect { x, y, width, height } => {
+                if width < 0 || height < 0 {
+                    return Err(ProtocolError::new(
+                        object_id,
+                        error::INVALID_INPUT,
+                        format!("invalid anchor rect size {}x{}", width, height),
+                    ));
+                }
+                self.placement.anchor_rect = Rect::new(x, y, width, height);
+            }
+            Request::SetAnchor { anchor } => {
+                self.placement.anchor = anchor;
+            }
+            Request::SetGravity { gravity } => {
+                self.placement.gravity = gravity;
+            }
+            Request::SetOffset { x, y } => {
+                self.placement.offset = (x, y);
+            }
+            Request::Destroy => {}
+            request => {
+                eprintln!("[xdg_positioner] Ignoring request: {:?}", request);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,98 +1,154 @@
+use crate::protocols::generated::{xdg_surface, xdg_wm_base};
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
-
-// --- Placeholders ---
-pub struct XdgToplevel;
-impl WaylandObject for XdgToplevel {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
-}
-pub struct XdgPopup;
-impl WaylandObject for XdgPopup {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
-}
-// ---
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
+use crate::protocols::xdg_popup::XdgPopup;
+use crate::protocols::xdg_toplevel::XdgToplevel;
+use crate::protocols::wl_surface::{SurfaceRole, WlSurface};
 
 /// Implements the xdg_surface object.
 /// This object is the bridge between a wl_surface and a desktop window role.
 pub struct XdgSurface {
+    id: u32,
+    // The xdg_wm_base that created this xdg_surface. Its errors, like
+    // invalid_popup_parent, are posted on it.
+    wm_base_id: u32,
     // The ID of the wl_surface this xdg_surface is associated with.
     // This would be set upon creation.
     surface_id: u32,
+    // The xdg_toplevel or xdg_popup created from this xdg_surface.
+    role_object_id: Option<u32>,
+}
+
+/// Sends xdg_surface.configure, which ends a configure sequence started
+/// by the role object. Returns the serial the client must ack.
+pub fn send_configure(client: &mut WaylandClient, xdg_surface_id: u32) -> u32 {
+    let serial = client.next_serial();
+    eprintln!("[xdg_surface] sending configure event with serial {}", serial);
+    xdg_surface::send_configure(client, xdg_surface_id, serial);
+    serial
 }
 
 impl XdgSurface {
-    pub fn new(surface_id: u32) -> Self {
-        Self { surface_id }
+    pub fn new(id: u32, wm_base_id: u32, surface_id: u32) -> Self {
+        Self { id, wm_base_id, surface_id, role_object_id: None }
     }
 
-    fn handle_get_toplevel(&self, client: &mut WaylandClient, new_toplevel_id: u32) {
-        println!("[xdg_surface] get_toplevel: creating toplevel {} for surface {}", new_toplevel_id, self.surface_id);
-
-        // This conceptually assigns the 'toplevel' role to the wl_surface.
-        // A real implementation would need a robust way to manage roles.
-        if client.get_surface_role(self.surface_id).is_some() {
-             println!("[xdg_surface] Error: surface {} already has a role.", self.surface_id);
-             // client.post_error(...)
-             return;
+    /// Whether the surface can take the role: it has no other role, and no
+    /// role object right now.
+    fn check_role(&self, client: &WaylandClient, role: SurfaceRole) -> Result<(), ProtocolError> {
+        let Some(surface) = client.get_object::<WlSurface>(self.surface_id) else {
+            return Ok(());
+        };
+        match (surface.role(), surface.role_object_id()) {
+            (Some(current), _) if current != role => Err(ProtocolError::new(
+                self.wm_base_id,
+                xdg_wm_base::error::ROLE,
+                format!("wl_surface {} already has the {} role", self.surface_id, current.name()),
+            )),
+            (_, Some(role_object_id)) => Err(ProtocolError::new(
+                self.id,
+                xdg_surface::error::ALREADY_CONSTRUCTED,
+                format!("wl_surface {} already has {} {}", self.surface_id, role.name(), role_object_id),
+            )),
+            _ => Ok(()),
         }
-        client.set_surface_role(self.surface_id, new_toplevel_id, "xdg_toplevel");
+    }
 
-        let toplevel = XdgToplevel {};
-        client.add_object(new_toplevel_id, Box::new(toplevel));
+    fn handle_get_toplevel(&mut self, client: &mut WaylandClient, new_toplevel_id: u32) -> Result<(), ProtocolError> {
+        eprintln!("[xdg_surface] get_toplevel: creating toplevel {} for surface {}", new_toplevel_id, self.surface_id);
 
-        // Immediately send a configure event to the client.
+        self.check_role(client, SurfaceRole::XdgToplevel)?;
+        client.set_surface_role(self.surface_id, SurfaceRole::XdgToplevel, Some(new_toplevel_id));
+
+        let toplevel = XdgToplevel::new(self.id, self.surface_id);
+        // The initial configure: the toplevel's state first, then the xdg_surface
+        // configure that the client has to ack.
+        toplevel.send_configure(client, new_toplevel_id);
+        client.add_object(new_toplevel_id, Box::new(toplevel));
+        self.role_object_id = Some(new_toplevel_id);
         self.send_configure(client);
-        // Also send a configure event to the new toplevel object.
-        // client.send_event(new_toplevel_id, XdgToplevel::Events::Configure { ... });
+        Ok(())
     }
 
-    fn handle_get_popup(&self, client: &mut WaylandClient, new_popup_id: u32) {
-        println!("[xdg_surface] get_popup: creating popup {}", new_popup_id);
-        // Similar logic to get_toplevel
-        let popup = XdgPopup {};
+    fn handle_get_popup(
+        &mut self,
+        client: &mut WaylandClient,
+        new_popup_id: u32,
+        parent: Option<u32>,
+        positioner: u32,
+    ) -> Result<(), ProtocolError> {
+        eprintln!("[xdg_surface] get_popup: creating popup {} for surface {}", new_popup_id, self.surface_id);
+
+        self.check_role(client, SurfaceRole::XdgPopup)?;
+        let parent_surface_id = match parent {
+            Some(parent) => match client.get_object::<XdgSurface>(parent) {
+                Some(parent) => Some(parent.surface_id),
+                None => {
+                    return Err(ProtocolError::new(
+                        self.wm_base_id,
+                        xdg_wm_base::error::INVALID_POPUP_PARENT,
+                        format!("popup parent {} is not an xdg_surface", parent),
+                    ));
+                }
+            },
+            None => None,
+        };
+
+        let popup = XdgPopup::new(client, self.wm_base_id, self.id, self.surface_id, parent_surface_id, positioner)?;
+        client.set_surface_role(self.surface_id, SurfaceRole::XdgPopup, Some(new_popup_id));
+        popup.send_configure(client, new_popup_id);
         client.add_object(new_popup_id, Box::new(popup));
+        self.role_object_id = Some(new_popup_id);
+        self.send_configure(client);
+        Ok(())
+    }
+
+    fn handle_destroy(&self, client: &mut WaylandClient) -> Result<(), ProtocolError> {
+        eprintln!("[xdg_surface] destroy");
+        // The role object has to be destroyed first.
+        match self.role_object_id {
+            Some(role_object_id) if client.has_object(role_object_id) => Err(ProtocolError::new(
+                self.id,
+                xdg_surface::error::DEFUNCT_ROLE_OBJECT,
+                format!("xdg_surface {} destroyed before its role object {}", self.id, role_object_id),
+            )),
+            _ => Ok(()),
+        }
     }
 
     fn handle_ack_configure(&self, serial: u32) {
-        println!("[xdg_surface] ack_configure: received ack for serial {}", serial);
+        eprintln!("[xdg_surface] ack_configure: received ack for serial {}", serial);
         // Here, the compositor knows the client has processed the configuration.
         // It might resolve a promise or future.
     }
 
     fn send_configure(&self, client: &mut WaylandClient) {
-        let serial = client.next_serial(); // conceptual method
-        println!("[xdg_surface] sending configure event with serial {}", serial);
-        // client.send_event(self.id, XdgSurface::Events::Configure { serial });
+        send_configure(client, self.id);
     }
 }
 
-impl WaylandObject for XdgSurface {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const DESTROY: u16 = 0;
-        const GET_TOPLEVEL: u16 = 1;
-        const GET_POPUP: u16 = 2;
-        const SET_WINDOW_GEOMETRY: u16 = 3;
-        const ACK_CONFIGURE: u16 = 4;
+impl RequestHandler for XdgSurface {
+    type Request = xdg_surface::Request;
 
-        match request.opcode {
-            GET_TOPLEVEL => {
-                let new_id = request.args.get(0).cloned().unwrap_or(0);
-                self.handle_get_toplevel(client, new_id);
+    fn handle_request(&mut self, client: &mut WaylandClient, _object_id: ObjectId, request: xdg_surface::Request) -> Result<(), ProtocolError> {
+        match request {
+            xdg_surface::Request::GetToplevel { id } => {
+                self.handle_get_toplevel(client, id)?;
             }
-            GET_POPUP => {
-                let new_id = request.args.get(0).cloned().unwrap_or(0);
-                self.handle_get_popup(client, new_id);
+            xdg_surface::Request::GetPopup { id, parent, positioner } => {
+                self.handle_get_popup(client, id, parent, positioner)?;
             }
-            ACK_CONFIGURE => {
-                let serial = request.args.get(0).cloned().unwrap_or(0);
+            xdg_surface::Request::AckConfigure { serial } => {
                 self.handle_ack_configure(serial);
             }
-            DESTROY => {
-                println!("[xdg_surface] destroy");
+            xdg_surface::Request::Destroy => {
+                self.handle_destroy(client)?;
             }
-            _ => {
-                 eprintln!("[xdg_surface] Received unknown opcode: {}", request.opcode);
+            request => {
+                 eprintln!("[xdg_surface] Unhandled request: {:?}", request);
             }
         }
+        Ok(())
     }
 }
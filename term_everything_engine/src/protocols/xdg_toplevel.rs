@@ -1,10 +1,17 @@
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
+use crate::protocols::generated::xdg_toplevel::{self, state, Request};
+use crate::protocols::xdg_surface;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::ObjectId;
 
 /// Implements the xdg_toplevel role object.
 /// This holds the state for a standard desktop window.
 #[derive(Default)]
 pub struct XdgToplevel {
+    // The xdg_surface this role was created from; it sends the final configure.
+    xdg_surface_id: u32,
+    // The wl_surface that has the xdg_toplevel role.
+    surface_id: u32,
     title: Option<String>,
     app_id: Option<String>,
     min_size: (i32, i32),
@@ -14,72 +21,92 @@ pub struct XdgToplevel {
 }
 
 impl XdgToplevel {
-    pub fn new() -> Self {
-        Default::default()
+    pub fn new(xdg_surface_id: u32, surface_id: u32) -> Self {
+        Self {
+            xdg_surface_id,
+            surface_id,
+            ..Default::default()
+        }
+    }
+
+    /// Sends xdg_toplevel.configure with the current state.
+    /// It only takes effect once followed by xdg_surface.configure.
+    pub fn send_configure(&self, client: &mut WaylandClient, self_id: u32) {
+        let mut states = Vec::new();
+        if self.maximized {
+            states.push(state::MAXIMIZED);
+        }
+        if self.fullscreen {
+            states.push(state::FULLSCREEN);
+        }
+        let states = states.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        // A size of 0x0 lets the client pick its own size.
+        xdg_toplevel::send_configure(client, self_id, 0, 0, states);
+    }
+
+    fn reconfigure(&self, client: &mut WaylandClient, self_id: u32) {
+        self.send_configure(client, self_id);
+        xdg_surface::send_configure(client, self.xdg_surface_id);
     }
 
     fn handle_set_title(&mut self, title: String) {
-        println!("[xdg_toplevel] set_title: {}", title);
+        eprintln!("[xdg_toplevel] set_title: {}", title);
         self.title = Some(title);
     }
 
     fn handle_set_app_id(&mut self, app_id: String) {
-        println!("[xdg_toplevel] set_app_id: {}", app_id);
+        eprintln!("[xdg_toplevel] set_app_id: {}", app_id);
         self.app_id = Some(app_id);
     }
 
-    fn handle_set_maximized(&mut self, client: &mut WaylandClient) {
-        println!("[xdg_toplevel] set_maximized");
+    fn handle_set_maximized(&mut self, client: &mut WaylandClient, self_id: u32) {
+        eprintln!("[xdg_toplevel] set_maximized");
         self.maximized = true;
-        // In a real implementation, this would trigger a re-configure of the surface.
-        // client.send_event(self.id, XdgToplevel::Events::Configure { ... });
+        self.reconfigure(client, self_id);
     }
 
-    fn handle_unset_maximized(&mut self, client: &mut WaylandClient) {
-        println!("[xdg_toplevel] unset_maximized");
+    fn handle_unset_maximized(&mut self, client: &mut WaylandClient, self_id: u32) {
+        eprintln!("[xdg_toplevel] unset_maximized");
         self.maximized = false;
-        // ... trigger re-configure
+        self.reconfigure(client, self_id);
     }
 }
 
-impl WaylandObject for XdgToplevel {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const DESTROY: u16 = 0;
-        const SET_PARENT: u16 = 1;
-        const SET_TITLE: u16 = 2;
-        const SET_APP_ID: u16 = 3;
-        const SHOW_WINDOW_MENU: u16 = 4;
-        const MOVE: u16 = 5;
-        const RESIZE: u16 = 6;
-        const SET_MAX_SIZE: u16 = 7;
-        const SET_MIN_SIZE: u16 = 8;
-        const SET_MAXIMIZED: u16 = 9;
-        const UNSET_MAXIMIZED: u16 = 10;
-        const SET_FULLSCREEN: u16 = 11;
-        const UNSET_FULLSCREEN: u16 = 12;
-        const SET_MINIMIZED: u16 = 13;
+impl RequestHandler for XdgToplevel {
+    type Request = Request;
 
-        match request.opcode {
-            SET_TITLE => {
-                // This is a simplification. The arg is a string, which isn't
-                // handled by the basic `Request` struct yet.
-                self.handle_set_title("...".to_string());
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: Request) -> Result<(), ProtocolError> {
+        match request {
+            Request::SetTitle { title } => {
+                self.handle_set_title(title);
+            }
+            Request::SetAppId { app_id } => {
+                self.handle_set_app_id(app_id);
+            }
+            Request::SetMaxSize { width, height } => {
+                self.max_size = (width, height);
             }
-            SET_APP_ID => {
-                self.handle_set_app_id("...".to_string());
+            Request::SetMinSize { width, height } => {
+                self.min_size = (width, height);
             }
-            SET_MAXIMIZED => {
-                self.handle_set_maximized(client);
+            Request::SetMaximized => {
+                self.handle_set_maximized(client, object_id);
             }
-            UNSET_MAXIMIZED => {
-                self.handle_unset_maximized(client);
+            Request::UnsetMaximized => {
+                self.handle_unset_maximized(client, object_id);
             }
-            DESTROY => {
-                println!("[xdg_toplevel] destroy");
+            Request::Destroy => {
+                eprintln!("[xdg_toplevel] destroy");
             }
-            _ => {
-                println!("[xdg_toplevel] Received unhandled opcode: {}", request.opcode);
+            request => {
+                eprintln!("[xdg_toplevel] Received unhandled request: {:?}", request);
             }
         }
+        Ok(())
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, _object_id: ObjectId) {
+        // Role teardown: the surface is unmapped and can get a new xdg_toplevel.
+        client.clear_surface_role_object(self.surface_id);
     }
 }
@@ -1,17 +1,11 @@
+use crate::protocols::generated::xdg_wm_base;
 use crate::wayland_client::WaylandClient;
-use crate::wayland_object::{Request, WaylandObject};
-
-// --- Placeholders ---
-pub struct XdgSurface;
-impl WaylandObject for XdgSurface {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
-}
-
-pub struct XdgPositioner;
-impl WaylandObject for XdgPositioner {
-    fn on_request(&mut self, _client: &mut WaylandClient, _request: Request) { /* ... */ }
-}
-// ---
+use crate::global_registry::Global;
+use crate::wayland_object::{ProtocolError, RequestHandler};
+use crate::wayland_types::{Interface, ObjectId};
+use crate::protocols::xdg_positioner::XdgPositioner;
+use crate::protocols::xdg_surface::XdgSurface;
+use crate::protocols::wl_surface::SurfaceRole;
 
 /// Implements the xdg_wm_base Wayland global.
 /// This is the entry point for the xdg-shell window management protocol.
@@ -28,63 +22,70 @@ impl XdgWmBase {
         self_id: u32,
         new_xdg_surface_id: u32,
         surface_id: u32,
-    ) {
-        println!("[xdg_wm_base] get_xdg_surface: creating xdg_surface {} for wl_surface {}", new_xdg_surface_id, surface_id);
+    ) -> Result<(), ProtocolError> {
+        eprintln!("[xdg_wm_base] get_xdg_surface: creating xdg_surface {} for wl_surface {}", new_xdg_surface_id, surface_id);
 
-        // This is a conceptual implementation of the role check.
-        // It requires a way to check and set a role on a wl_surface.
-        if client.get_surface_role(surface_id).is_some() {
-            println!("[xdg_wm_base] Error: wl_surface {} already has a role.", surface_id);
-            // client.post_error(self_id, xdg_wm_base_error::ROLE, "surface already has a role");
-            return;
+        // xdg_surface is not a role itself, but the surface must not have a non-xdg role.
+        if let Some(role @ (SurfaceRole::Subsurface | SurfaceRole::Cursor)) = client.get_surface_role(surface_id) {
+            return Err(ProtocolError::new(
+                self_id,
+                xdg_wm_base::error::ROLE,
+                format!("wl_surface {} already has the {} role", surface_id, role.name()),
+            ));
         }
 
-        // Associate the surface with the new xdg_surface role.
-        client.set_surface_role(surface_id, new_xdg_surface_id, "xdg_surface");
-
-        let xdg_surface = XdgSurface {};
+        let xdg_surface = XdgSurface::new(new_xdg_surface_id, self_id, surface_id);
         client.add_object(new_xdg_surface_id, Box::new(xdg_surface));
+        Ok(())
     }
 
     fn handle_create_positioner(&self, client: &mut WaylandClient, new_positioner_id: u32) {
-        println!("[xdg_wm_base] create_positioner: creating positioner {}", new_positioner_id);
-        let positioner = XdgPositioner {};
+        eprintln!("[xdg_wm_base] create_positioner: creating positioner {}", new_positioner_id);
+        let positioner = XdgPositioner::new();
         client.add_object(new_positioner_id, Box::new(positioner));
     }
 
     fn handle_pong(&self, _serial: u32) {
         // A client sends this in response to a ping event.
-        println!("[xdg_wm_base] pong received");
+        eprintln!("[xdg_wm_base] pong received");
     }
 }
 
-impl WaylandObject for XdgWmBase {
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request) {
-        const DESTROY: u16 = 0;
-        const CREATE_POSITIONER: u16 = 1;
-        const GET_XDG_SURFACE: u16 = 2;
-        const PONG: u16 = 3;
+impl RequestHandler for XdgWmBase {
+    type Request = xdg_wm_base::Request;
 
-        match request.opcode {
-            GET_XDG_SURFACE => {
-                let new_id = request.args.get(0).cloned().unwrap_or(0);
-                let surface_id = request.args.get(1).cloned().unwrap_or(0);
-                self.handle_get_xdg_surface(client, request.object_id, new_id, surface_id);
+    fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: xdg_wm_base::Request) -> Result<(), ProtocolError> {
+        match request {
+            xdg_wm_base::Request::GetXdgSurface { id, surface } => {
+                self.handle_get_xdg_surface(client, object_id, id, surface)?;
             }
-            CREATE_POSITIONER => {
-                 let new_id = request.args.get(0).cloned().unwrap_or(0);
-                 self.handle_create_positioner(client, new_id);
+            xdg_wm_base::Request::CreatePositioner { id } => {
+                 self.handle_create_positioner(client, id);
             }
-            PONG => {
-                let serial = request.args.get(0).cloned().unwrap_or(0);
+            xdg_wm_base::Request::Pong { serial } => {
                 self.handle_pong(serial);
             }
-            DESTROY => {
-                println!("[xdg_wm_base] destroy");
-            }
-            _ => {
-                eprintln!("[xdg_wm_base] Received unknown opcode: {}", request.opcode);
+            xdg_wm_base::Request::Destroy => {
+                eprintln!("[xdg_wm_base] destroy");
             }
         }
+        Ok(())
+    }
+}
+
+/// Advertises xdg_wm_base.
+pub struct XdgWmBaseGlobal;
+
+impl Global for XdgWmBaseGlobal {
+    fn interface(&self) -> &'static Interface {
+        &xdg_wm_base::INTERFACE
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+        client.add_object(id, Box::new(XdgWmBase::new()));
     }
 }
@@ -0,0 +1,95 @@
+/// An axis-aligned rectangle in surface or buffer coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    /// The overlap of the two, if any.
+    /// Clients often send huge rects (damage 0, 0, INT32_MAX, INT32_MAX), so this can't overflow.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = (self.x as i64 + self.width as i64).min(other.x as i64 + other.width as i64);
+        let bottom = (self.y as i64 + self.height as i64).min(other.y as i64 + other.height as i64);
+        if right <= left as i64 || bottom <= top as i64 {
+            return None;
+        }
+        Some(Rect::new(left, top, (right - left as i64) as i32, (bottom - top as i64) as i32))
+    }
+
+    /// The smallest rect containing both.
+    pub fn bounding(&self, other: &Rect) -> Rect {
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = (self.x as i64 + self.width as i64).max(other.x as i64 + other.width as i64);
+        let bottom = (self.y as i64 + self.height as i64).max(other.y as i64 + other.height as i64);
+        Rect::new(
+            left,
+            top,
+            (right - left as i64).min(i32::MAX as i64) as i32,
+            (bottom - top as i64).min(i32::MAX as i64) as i32,
+        )
+    }
+}
+
+/// Past this many rects a region is collapsed into its bounding box.
+/// Copying a bit too much is cheaper than tracking many small rects.
+const MAX_RECTS: usize = 16;
+
+/// A set of rectangles, e.g. the damage of a surface.
+/// It is not kept minimal: overlapping rects are allowed.
+#[derive(Debug, Default, Clone)]
+pub struct Region {
+    rects: Vec<Rect>,
+}
+
+impl Region {
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        // Already covered, e.g. the same full damage sent twice.
+        if self.rects.iter().any(|r| r.intersect(&rect) == Some(rect)) {
+            return;
+        }
+        self.rects.retain(|r| rect.intersect(r) != Some(*r));
+        self.rects.push(rect);
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self.rects.iter().skip(1).fold(self.rects[0], |bounds, r| bounds.bounding(r));
+            self.rects = vec![bounds];
+        }
+    }
+
+    pub fn union(&mut self, other: &Region) {
+        for rect in &other.rects {
+            self.add(*rect);
+        }
+    }
+
+    /// The parts of the region inside `bounds`.
+    pub fn clip(&self, bounds: &Rect) -> Region {
+        Region {
+            rects: self.rects.iter().filter_map(|r| r.intersect(bounds)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+}
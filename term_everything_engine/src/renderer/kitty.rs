@@ -0,0 +1,406 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::region::Rect;
+use crate::renderer::{fit_cells, Frame, Renderer};
+use term_size::TermSize;
+
+/// Every frame goes into this image, so the terminal never piles up old ones.
+const IMAGE_ID: u32 = 0x7e11_0001;
+
+/// Tells the answer to the medium query apart from anything else on stdin.
+const QUERY_ID: u32 = 0x7e11_0002;
+
+/// How long to wait for the terminal to answer the medium query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Damage is tracked in squares of this many pixels.
+const TILE_SIZE: u32 = 64;
+
+/// The most base64 a single escape sequence may carry.
+const CHUNK_SIZE: usize = 4096;
+
+/// How pixel data gets to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Medium {
+    /// Inside the escape sequences, zlib-compressed. Works over ssh.
+    Direct,
+    /// A temporary file the terminal reads and deletes (t=t).
+    TempFile,
+    /// A POSIX shared memory object the terminal reads and unlinks (t=s).
+    SharedMemory,
+}
+
+/// A file or shared memory object handed to the terminal, which removes it
+/// once it has read it.
+enum Transfer {
+    File(PathBuf),
+    SharedMemory(CString),
+}
+
+impl Transfer {
+    /// What goes in the payload of the graphics command.
+    fn name(&self) -> &[u8] {
+        match self {
+            Transfer::File(path) => path.as_os_str().as_encoded_bytes(),
+            Transfer::SharedMemory(name) => name.as_bytes(),
+        }
+    }
+
+    fn exists(&self) -> bool {
+        match self {
+            Transfer::File(path) => path.exists(),
+            Transfer::SharedMemory(name) => {
+                let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+                if fd >= 0 {
+                    unsafe { libc::close(fd) };
+                }
+                fd >= 0
+            }
+        }
+    }
+
+    fn remove(&self) {
+        match self {
+            Transfer::File(path) => {
+                let _ = std::fs::remove_file(path);
+            }
+            Transfer::SharedMemory(name) => {
+                unsafe { libc::shm_unlink(name.as_ptr()) };
+            }
+        }
+    }
+}
+
+/// Draws frames with the kitty graphics protocol, at full resolution.
+/// The whole image is only sent when the canvas or terminal size changes;
+/// after that, only the tiles that changed are replaced in place.
+pub struct KittyRenderer {
+    medium: Medium,
+    // The frame and canvas size of the image on the terminal.
+    placed: Option<(u32, u32, usize, usize)>,
+    // What the image on the terminal looks like.
+    previous: Vec<u8>,
+    // Names the files and shared memory objects.
+    next_transfer: u64,
+    // Transfers the terminal may not have read yet, removed on drop.
+    unread: Vec<Transfer>,
+}
+
+impl KittyRenderer {
+    pub fn new(medium: Medium) -> Self {
+        Self {
+            medium,
+            placed: None,
+            previous: Vec::new(),
+            next_transfer: 0,
+            unread: Vec::new(),
+        }
+    }
+
+    /// Reads $TERM_EVERYTHING_KITTY_MEDIUM (DIRECT, FILE or SHM).
+    /// Without it, shared memory when the terminal is on this machine, else direct.
+    /// Files and shared memory are only used if the terminal says it can read them.
+    pub fn from_env() -> Self {
+        let medium = match std::env::var("TERM_EVERYTHING_KITTY_MEDIUM").as_deref() {
+            Ok("DIRECT") => Medium::Direct,
+            Ok("FILE") => Medium::TempFile,
+            Ok("SHM") => Medium::SharedMemory,
+            _ if ["SSH_CONNECTION", "SSH_CLIENT", "SSH_TTY"].iter().any(|name| std::env::var_os(name).is_some()) => {
+                Medium::Direct
+            }
+            _ => Medium::SharedMemory,
+        };
+        let mut renderer = Self::new(medium);
+        if medium != Medium::Direct && !renderer.query_medium() {
+            eprintln!("[KittyRenderer] The terminal can't read {:?}, sending pixels directly", medium);
+            renderer.medium = Medium::Direct;
+        }
+        renderer
+    }
+
+    /// Puts `data` where the terminal can read it, for the local media.
+    fn write_transfer(&mut self, data: &[u8]) -> Option<Transfer> {
+        let transfer = self.next_transfer;
+        self.next_transfer += 1;
+        match self.medium {
+            Medium::Direct => None,
+            Medium::TempFile => write_temp_file(transfer, data),
+            Medium::SharedMemory => write_shared_memory(transfer, data),
+        }
+    }
+
+    /// Asks the terminal to check, but not keep, a 1x1 image sent through
+    /// the medium (a=q). Terminals without the graphics protocol only answer
+    /// the device attributes request sent after it, so they take no time.
+    fn query_medium(&mut self) -> bool {
+        let stdin = libc::STDIN_FILENO;
+        if unsafe { libc::isatty(stdin) == 0 || libc::isatty(libc::STDOUT_FILENO) == 0 } {
+            return false;
+        }
+        let Some(transfer) = self.write_transfer(&[0, 0, 0]) else {
+            return false;
+        };
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(stdin, &mut original) } != 0 {
+            transfer.remove();
+            return false;
+        }
+        // The answer is read as it comes, without showing it.
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        unsafe { libc::tcsetattr(stdin, libc::TCSANOW, &raw) };
+
+        let medium = if self.medium == Medium::TempFile { 't' } else { 's' };
+        let query = format!(
+            "\x1b_Ga=q,i={},s=1,v=1,f=24,t={};{}\x1b\\\x1b[c",
+            QUERY_ID,
+            medium,
+            BASE64.encode(transfer.name())
+        );
+        let mut stdout = std::io::stdout().lock();
+        let sent = stdout.write_all(query.as_bytes()).and_then(|()| stdout.flush()).is_ok();
+        let reply = if sent { read_reply(stdin) } else { Vec::new() };
+
+        unsafe { libc::tcsetattr(stdin, libc::TCSANOW, &original) };
+        // Gone already if the terminal read it.
+        transfer.remove();
+        is_ok_reply(&reply)
+    }
+
+    /// Sends `data` with the graphics command `keys`.
+    /// A local transfer that fails falls back to the escape sequences.
+    fn transmit(&mut self, out: &mut Vec<u8>, keys: &str, data: &[u8]) {
+        if let Some(transfer) = self.write_transfer(data) {
+            let medium = if self.medium == Medium::TempFile { 't' } else { 's' };
+            write!(out, "\x1b_G{},t={},S={};{}\x1b\\", keys, medium, data.len(), BASE64.encode(transfer.name())).unwrap();
+            // The terminal keeps up, so this stays short.
+            self.unread.retain(Transfer::exists);
+            self.unread.push(transfer);
+            return;
+        }
+
+        // Compressing only pays off when the bytes go through the terminal's input.
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        let payload = BASE64.encode(encoder.finish().unwrap());
+        let mut chunks = payload.as_bytes().chunks(CHUNK_SIZE).peekable();
+        let mut first = true;
+        while let Some(chunk) = chunks.next() {
+            let more = chunks.peek().is_some() as u8;
+            if first {
+                write!(out, "\x1b_G{},o=z,m={};", keys, more).unwrap();
+                first = false;
+            } else {
+                write!(out, "\x1b_Gm={};", more).unwrap();
+            }
+            out.extend_from_slice(chunk);
+            out.extend_from_slice(b"\x1b\\");
+        }
+    }
+}
+
+impl Drop for KittyRenderer {
+    fn drop(&mut self) {
+        for transfer in &self.unread {
+            transfer.remove();
+        }
+    }
+}
+
+/// Reads what the terminal sends back until the device attributes answer
+/// (ESC [ ? ... c), which comes last, or the timeout.
+fn read_reply(fd: i32) -> Vec<u8> {
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut reply = Vec::new();
+    loop {
+        let answered = reply
+            .windows(3)
+            .position(|bytes| bytes == b"\x1b[?")
+            .is_some_and(|start| reply[start..].contains(&b'c'));
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if answered || remaining.is_zero() {
+            return reply;
+        }
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as i32) } <= 0 {
+            return reply;
+        }
+        let mut buffer = [0u8; 256];
+        let length = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if length <= 0 {
+            return reply;
+        }
+        reply.extend_from_slice(&buffer[..length as usize]);
+    }
+}
+
+/// Whether the terminal answered the medium query with OK.
+fn is_ok_reply(reply: &[u8]) -> bool {
+    let ok = format!("\x1b_Gi={};OK\x1b\\", QUERY_ID);
+    reply.windows(ok.len()).any(|bytes| bytes == ok.as_bytes())
+}
+
+/// Writes a file for t=t. The terminal only deletes files whose names say
+/// they are for the graphics protocol.
+fn write_temp_file(transfer: u64, data: &[u8]) -> Option<Transfer> {
+    let path = std::env::temp_dir().join(format!(
+        "term-everything-tty-graphics-protocol-{}-{}",
+        std::process::id(),
+        transfer
+    ));
+    match std::fs::write(&path, data) {
+        Ok(()) => Some(Transfer::File(path)),
+        Err(e) => {
+            eprintln!("[KittyRenderer] Failed to write {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Creates a shared memory object for t=s.
+fn write_shared_memory(transfer: u64, data: &[u8]) -> Option<Transfer> {
+    let name = format!("/term-everything-tty-graphics-protocol-{}-{}", std::process::id(), transfer);
+    let c_name = CString::new(name.as_str()).unwrap();
+    let fd = unsafe { libc::shm_open(c_name.as_ptr(), libc::O_CREAT | libc::O_EXCL | libc::O_RDWR, 0o600) };
+    if fd < 0 {
+        eprintln!("[KittyRenderer] Failed to create {}: {}", name, std::io::Error::last_os_error());
+        return None;
+    }
+    // Closed when dropped.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    if let Err(e) = file.write_all(data) {
+        eprintln!("[KittyRenderer] Failed to write {}: {}", name, e);
+        unsafe { libc::shm_unlink(c_name.as_ptr()) };
+        return None;
+    }
+    Some(Transfer::SharedMemory(c_name))
+}
+
+/// The tiles where `current` differs from `previous`, merged into runs along each row of tiles.
+fn damaged_rects(previous: &[u8], current: &[u8], width: u32, height: u32) -> Vec<Rect> {
+    let stride = width as usize * 4;
+    let mut rects = Vec::new();
+    for tile_y in (0..height).step_by(TILE_SIZE as usize) {
+        let tile_height = TILE_SIZE.min(height - tile_y);
+        let mut run: Option<Rect> = None;
+        for tile_x in (0..width).step_by(TILE_SIZE as usize) {
+            let tile_width = TILE_SIZE.min(width - tile_x);
+            let damaged = (tile_y..tile_y + tile_height).any(|y| {
+                let start = y as usize * stride + tile_x as usize * 4;
+                let end = start + tile_width as usize * 4;
+                previous[start..end] != current[start..end]
+            });
+            match (&mut run, damaged) {
+                (Some(rect), true) => rect.width += tile_width as i32,
+                (None, true) => run = Some(Rect::new(tile_x as i32, tile_y as i32, tile_width as i32, tile_height as i32)),
+                (_, false) => rects.extend(run.take()),
+            }
+        }
+        rects.extend(run);
+    }
+    rects
+}
+
+/// The pixels of `rect`, rows packed together.
+fn copy_rect(frame: &Frame, rect: Rect) -> Vec<u8> {
+    let stride = frame.width as usize * 4;
+    let row_bytes = rect.width as usize * 4;
+    let mut data = Vec::with_capacity(row_bytes * rect.height as usize);
+    for y in rect.y as usize..(rect.y + rect.height) as usize {
+        let start = y * stride + rect.x as usize * 4;
+        data.extend_from_slice(&frame.pixels[start..start + row_bytes]);
+    }
+    data
+}
+
+impl Renderer for KittyRenderer {
+    fn render(&mut self, frame: &Frame, term_size: &TermSize, out: &mut Vec<u8>) {
+        let (width_cells, height_cells) = fit_cells(frame, term_size);
+        let placement = (frame.width, frame.height, width_cells, height_cells);
+
+        if self.placed != Some(placement) {
+            if self.placed.is_some() {
+                write!(out, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", IMAGE_ID).unwrap();
+            }
+            // Shown at the top left, scaled to the canvas, without moving the cursor.
+            out.extend_from_slice(b"\x1b[H");
+            let keys = format!(
+                "a=T,i={},p=1,f=32,s={},v={},c={},r={},C=1,q=2",
+                IMAGE_ID, frame.width, frame.height, width_cells, height_cells
+            );
+            self.transmit(out, &keys, frame.pixels);
+            self.previous = frame.pixels.to_vec();
+            self.placed = Some(placement);
+            return;
+        }
+
+        // Replace the pixels of the changed tiles in the image's only frame.
+        for rect in damaged_rects(&self.previous, frame.pixels, frame.width, frame.height) {
+            let keys = format!(
+                "a=f,i={},r=1,x={},y={},s={},v={},f=32,X=1,q=2",
+                IMAGE_ID, rect.x, rect.y, rect.width, rect.height
+            );
+            self.transmit(out, &keys, &copy_rect(frame, rect));
+        }
+        self.previous.copy_from_slice(frame.pixels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_is_merged_into_runs_of_tiles() {
+        // Three tiles across, the last two pixels wide; two down, the last six high.
+        let (width, height) = (130, 70);
+        let previous = vec![0; width * height * 4];
+        assert!(damaged_rects(&previous, &previous, width as u32, height as u32).is_empty());
+
+        let mut current = previous.clone();
+        for (x, y) in [(0, 0), (70, 10), (129, 69)] {
+            current[(y * width + x) * 4] = 255;
+        }
+        assert_eq!(
+            damaged_rects(&previous, &current, width as u32, height as u32),
+            [Rect::new(0, 0, 128, 64), Rect::new(128, 64, 2, 6)]
+        );
+
+        // An undamaged tile in between splits the run.
+        let mut current = previous.clone();
+        current[(64 * width + 129) * 4] = 255;
+        current[(64 * width) * 4] = 255;
+        assert_eq!(
+            damaged_rects(&previous, &current, width as u32, height as u32),
+            [Rect::new(0, 64, 64, 6), Rect::new(128, 64, 2, 6)]
+        );
+    }
+
+    #[test]
+    fn copies_the_rows_of_a_rect() {
+        // Pixel n is four bytes of n.
+        let pixels: Vec<u8> = (0..3 * 2).flat_map(|n| [n; 4]).collect();
+        let frame = Frame { pixels: &pixels, width: 3, height: 2 };
+        let data = copy_rect(&frame, Rect::new(1, 0, 2, 2));
+        assert_eq!(data, [1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn only_ok_confirms_the_medium() {
+        let reply = |text: String| text.into_bytes();
+        assert!(is_ok_reply(&reply(format!("\x1b_Gi={};OK\x1b\\\x1b[?62;4c", QUERY_ID))));
+        assert!(!is_ok_reply(&reply(format!("\x1b_Gi={};EBADF:no such file\x1b\\\x1b[?62c", QUERY_ID))));
+        // Only the device attributes, from a terminal without the protocol.
+        assert!(!is_ok_reply(b"\x1b[?62;22c"));
+    }
+}
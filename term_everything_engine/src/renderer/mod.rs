@@ -0,0 +1,52 @@
+pub mod kitty;
+pub mod sixel;
+#[cfg(feature = "symbols-renderer")]
+pub mod symbols;
+
+use term_size::TermSize;
+
+/// A composited frame of the virtual monitor: RGBA8 pixels, width * 4 bytes per row.
+pub struct Frame<'a> {
+    pub pixels: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Turns frames into output for the terminal.
+/// The chafa path in rust_interop is used when no renderer is built in.
+pub trait Renderer: Send {
+    /// Appends what draws `frame` on a terminal of `term_size` to `out`.
+    fn render(&mut self, frame: &Frame, term_size: &TermSize, out: &mut Vec<u8>);
+}
+
+/// An unknown terminal size is the classic 80x24.
+pub const FALLBACK_WIDTH_CELLS: i32 = 80;
+pub const FALLBACK_HEIGHT_CELLS: i32 = 24;
+
+/// The most cells the frame fits in without changing its aspect ratio,
+/// like chafa_calc_canvas_geometry.
+pub fn fit_cells(frame: &Frame, term_size: &TermSize) -> (usize, usize) {
+    let max_width = if term_size.width_cells > 0 { term_size.width_cells } else { FALLBACK_WIDTH_CELLS } as f32;
+    let max_height = if term_size.height_cells > 0 { term_size.height_cells } else { FALLBACK_HEIGHT_CELLS } as f32;
+    // A cell is font_ratio times as wide as it is tall.
+    let aspect = frame.width as f32 / frame.height.max(1) as f32 / term_size.font_ratio;
+    let (width, height) = if max_width / aspect <= max_height {
+        (max_width, max_width / aspect)
+    } else {
+        (max_height * aspect, max_height)
+    };
+    (width.round().max(1.0) as usize, height.round().max(1.0) as usize)
+}
+
+/// The built-in renderer for $TERM_EVERYTHING_PIXEL_MODE, if there is one.
+/// May block while the kitty renderer queries the terminal.
+pub fn from_env() -> Option<Box<dyn Renderer>> {
+    let pixel_mode = std::env::var("TERM_EVERYTHING_PIXEL_MODE").ok();
+    match pixel_mode.as_deref() {
+        Some("KITTY") => Some(Box::new(kitty::KittyRenderer::from_env())),
+        Some("SIXELS") => Some(Box::new(sixel::SixelRenderer::from_env())),
+        #[cfg(feature = "symbols-renderer")]
+        None | Some("SYMBOLS") => Some(Box::new(symbols::SymbolRenderer::from_env())),
+        _ => None,
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::Write;
+
+use crate::renderer::{fit_cells, Frame, Renderer, FALLBACK_HEIGHT_CELLS};
+use term_size::TermSize;
+
+/// Sixel terminals commonly have this many color registers.
+const MAX_COLORS: usize = 256;
+
+/// Colors are quantized to 5 bits per channel before building a palette.
+const KEY_COUNT: usize = 1 << 15;
+
+/// A palette keeps being used while it matches frames this well: the
+/// average squared distance of a pixel to its palette color.
+const REUSE_ERROR: u64 = 3 * 6 * 6;
+
+/// Not mapped to a palette color yet.
+const UNMAPPED: u8 = u8::MAX;
+
+type Rgb = [u8; 3];
+
+fn key(pixel: &[u8]) -> u16 {
+    ((pixel[0] as u16 >> 3) << 10) | ((pixel[1] as u16 >> 3) << 5) | (pixel[2] as u16 >> 3)
+}
+
+fn key_color(key: u16) -> Rgb {
+    let expand = |v: u16| ((v << 3) | (v >> 2)) as u8;
+    [expand(key >> 10), expand((key >> 5) & 0x1f), expand(key & 0x1f)]
+}
+
+fn distance(a: Rgb, b: Rgb) -> u64 {
+    (0..3).map(|c| (a[c] as i64 - b[c] as i64).pow(2) as u64).sum()
+}
+
+/// Quantized colors that become one palette color.
+struct ColorBox {
+    keys: Vec<u16>,
+    // The channel whose values are furthest apart, and how far.
+    channel: usize,
+    range: u8,
+}
+
+impl ColorBox {
+    fn new(keys: Vec<u16>) -> Self {
+        let (channel, range) = (0..3)
+            .map(|c| {
+                let values = keys.iter().map(|&key| key_color(key)[c]);
+                (c, values.clone().max().unwrap_or(0) - values.min().unwrap_or(0))
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap();
+        Self { keys, channel, range }
+    }
+}
+
+/// An adaptive palette and the palette index of every quantized color.
+struct Palette {
+    colors: Vec<Rgb>,
+    lookup: Vec<u8>,
+}
+
+impl Palette {
+    /// Median cut over the frame's color histogram.
+    fn new(histogram: &[u32]) -> Self {
+        let keys: Vec<u16> = (0..KEY_COUNT as u16).filter(|&key| histogram[key as usize] > 0).collect();
+        let mut boxes = vec![ColorBox::new(keys)];
+        // Index 255 is left for UNMAPPED.
+        while boxes.len() < MAX_COLORS - 1 {
+            // Split the box with the widest channel range at its median pixel.
+            let Some(widest) = boxes.iter_mut().filter(|b| b.range > 0).max_by_key(|b| b.range) else {
+                break;
+            };
+            let channel = widest.channel;
+            let keys = &mut widest.keys;
+            keys.sort_by_key(|&key| key_color(key)[channel]);
+            let total: u64 = keys.iter().map(|&key| histogram[key as usize] as u64).sum();
+            let mut seen = 0;
+            let median = keys
+                .iter()
+                .position(|&key| {
+                    seen += histogram[key as usize] as u64;
+                    seen * 2 >= total
+                })
+                .unwrap();
+            // Both halves keep at least one color.
+            let upper = keys.split_off((median + 1).min(keys.len() - 1));
+            *widest = ColorBox::new(std::mem::take(keys));
+            boxes.push(ColorBox::new(upper));
+        }
+
+        let colors = boxes
+            .iter()
+            .map(|color_box| {
+                let mut sum = [0u64; 3];
+                let mut count = 0u64;
+                for &key in &color_box.keys {
+                    let weight = histogram[key as usize] as u64;
+                    let color = key_color(key);
+                    for c in 0..3 {
+                        sum[c] += color[c] as u64 * weight;
+                    }
+                    count += weight;
+                }
+                sum.map(|s| (s / count.max(1)) as u8)
+            })
+            .collect();
+        Self {
+            colors,
+            lookup: vec![UNMAPPED; KEY_COUNT],
+        }
+    }
+
+    /// The index of the nearest palette color.
+    fn index(&mut self, key: u16) -> u8 {
+        let index = &mut self.lookup[key as usize];
+        if *index == UNMAPPED {
+            let color = key_color(key);
+            *index = (0..self.colors.len()).min_by_key(|&i| distance(self.colors[i], color)).unwrap() as u8;
+        }
+        *index
+    }
+
+    /// The average squared distance of the frame's pixels to their palette colors.
+    fn error(&mut self, histogram: &[u32]) -> u64 {
+        let mut error = 0;
+        let mut pixels = 0;
+        for key in 0..KEY_COUNT as u16 {
+            let count = histogram[key as usize] as u64;
+            if count > 0 {
+                let index = self.index(key) as usize;
+                error += distance(self.colors[index], key_color(key)) * count;
+                pixels += count;
+            }
+        }
+        error / pixels.max(1)
+    }
+}
+
+/// Whether the whole image is sent every frame, or only the rows of cells that changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redraw {
+    Full,
+    Damage,
+}
+
+/// Draws frames as Sixel images, quantized to an adaptive palette.
+/// The palette is kept across frames while it still fits them, which keeps
+/// colors from flickering and lets unchanged rows of cells be skipped.
+pub struct SixelRenderer {
+    redraw: Redraw,
+    palette: Option<Palette>,
+    // The palette indices of the image on the terminal, and its size.
+    previous: Vec<u8>,
+    previous_size: (usize, usize),
+    keys: Vec<u16>,
+    histogram: Vec<u32>,
+    indices: Vec<u8>,
+}
+
+impl SixelRenderer {
+    pub fn new(redraw: Redraw) -> Self {
+        Self {
+            redraw,
+            palette: None,
+            previous: Vec::new(),
+            previous_size: (0, 0),
+            keys: Vec::new(),
+            histogram: vec![0; KEY_COUNT],
+            indices: Vec::new(),
+        }
+    }
+
+    /// Reads $TERM_EVERYTHING_SIXEL_REDRAW (FULL or DAMAGE, the default).
+    pub fn from_env() -> Self {
+        match std::env::var("TERM_EVERYTHING_SIXEL_REDRAW").as_deref() {
+            Ok("FULL") => Self::new(Redraw::Full),
+            _ => Self::new(Redraw::Damage),
+        }
+    }
+
+    /// Scales the frame to `width` by `height` and quantizes it to 15 bits.
+    fn quantize(&mut self, frame: &Frame, width: usize, height: usize) {
+        let (frame_width, frame_height) = (frame.width as usize, frame.height as usize);
+        self.keys.clear();
+        self.histogram.fill(0);
+        for y in 0..height {
+            let row = y * frame_height / height * frame_width;
+            for x in 0..width {
+                let start = (row + x * frame_width / width) * 4;
+                let key = key(&frame.pixels[start..start + 3]);
+                self.keys.push(key);
+                self.histogram[key as usize] += 1;
+            }
+        }
+    }
+}
+
+/// Appends a Sixel image of `rows` of the indexed image.
+fn write_sixel(out: &mut Vec<u8>, palette: &Palette, indices: &[u8], width: usize, rows: std::ops::Range<usize>) {
+    // P2=1: pixels that are not drawn keep what the terminal showed.
+    write!(out, "\x1bP0;1;0q\"1;1;{};{}", width, rows.len()).unwrap();
+
+    let mut used = [false; MAX_COLORS];
+    for &index in &indices[rows.start * width..rows.end * width] {
+        used[index as usize] = true;
+    }
+    for (index, color) in palette.colors.iter().enumerate().filter(|&(index, _)| used[index]) {
+        let percent = |v: u8| (v as u32 * 100 + 127) / 255;
+        write!(out, "#{};2;{};{};{}", index, percent(color[0]), percent(color[1]), percent(color[2])).unwrap();
+    }
+
+    let mut sixels = vec![0u8; width];
+    for band in rows.clone().step_by(6) {
+        let band_rows = band..(band + 6).min(rows.end);
+        let mut in_band = [false; MAX_COLORS];
+        for &index in &indices[band_rows.start * width..band_rows.end * width] {
+            in_band[index as usize] = true;
+        }
+        let mut first = true;
+        for color in (0..MAX_COLORS).filter(|&color| in_band[color]) {
+            sixels.fill(0);
+            for (bit, y) in band_rows.clone().enumerate() {
+                for (x, &index) in indices[y * width..(y + 1) * width].iter().enumerate() {
+                    if index as usize == color {
+                        sixels[x] |= 1 << bit;
+                    }
+                }
+            }
+            // Back to the start of the band for every color after the first.
+            if !first {
+                out.push(b'$');
+            }
+            first = false;
+            write!(out, "#{}", color).unwrap();
+            // Empty sixels at the end of a line draw nothing.
+            let end = sixels.iter().rposition(|&sixel| sixel != 0).map_or(0, |last| last + 1);
+            write_run_length(out, &sixels[..end]);
+        }
+        out.push(b'-');
+    }
+    out.extend_from_slice(b"\x1b\\");
+}
+
+/// Writes sixels, with repeats of more than three as !<count><sixel>.
+fn write_run_length(out: &mut Vec<u8>, sixels: &[u8]) {
+    let mut x = 0;
+    while x < sixels.len() {
+        let run = sixels[x..].iter().take_while(|&&sixel| sixel == sixels[x]).count();
+        let character = 63 + sixels[x];
+        if run > 3 {
+            write!(out, "!{}{}", run, character as char).unwrap();
+        } else {
+            out.extend(std::iter::repeat_n(character, run));
+        }
+        x += run;
+    }
+}
+
+impl Renderer for SixelRenderer {
+    fn render(&mut self, frame: &Frame, term_size: &TermSize, out: &mut Vec<u8>) {
+        // One row short of the terminal, so drawing the last band cannot scroll it.
+        let height_cells = if term_size.height_cells > 0 { term_size.height_cells } else { FALLBACK_HEIGHT_CELLS };
+        let fit_size = TermSize {
+            height_cells: height_cells - 1,
+            ..*term_size
+        };
+        let (width_cells, height_cells) = fit_cells(frame, &fit_size);
+        // Without the cell size, sixels are drawn one per frame pixel.
+        let cell_height = term_size.height_of_a_cell_in_pixels;
+        let (width, height) = if term_size.width_of_a_cell_in_pixels > 0 && cell_height > 0 {
+            (width_cells * term_size.width_of_a_cell_in_pixels as usize, height_cells * cell_height as usize)
+        } else {
+            (frame.width as usize, frame.height as usize)
+        };
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.quantize(frame, width, height);
+
+        let reused = match &mut self.palette {
+            Some(palette) => palette.error(&self.histogram) <= REUSE_ERROR,
+            None => false,
+        };
+        if !reused {
+            self.palette = Some(Palette::new(&self.histogram));
+        }
+        let palette = self.palette.as_mut().unwrap();
+        self.indices.clear();
+        self.indices.extend(self.keys.iter().map(|&key| palette.index(key)));
+
+        let full = !reused || self.redraw == Redraw::Full || cell_height <= 0 || self.previous_size != (width, height);
+        if full {
+            out.extend_from_slice(b"\x1b[H");
+            write_sixel(out, palette, &self.indices, width, 0..height);
+        } else {
+            // Redraw runs of changed rows of cells, each starting where its first cell row is.
+            let cell_height = cell_height as usize;
+            let changed = |row: usize| {
+                let pixels = row * cell_height * width..((row + 1) * cell_height).min(height) * width;
+                self.previous[pixels.clone()] != self.indices[pixels]
+            };
+            let mut row = 0;
+            while row * cell_height < height {
+                if !changed(row) {
+                    row += 1;
+                    continue;
+                }
+                let start = row;
+                while (row + 1) * cell_height < height && changed(row + 1) {
+                    row += 1;
+                }
+                write!(out, "\x1b[{};1H", start + 1).unwrap();
+                write_sixel(out, palette, &self.indices, width, start * cell_height..((row + 1) * cell_height).min(height));
+                row += 1;
+            }
+        }
+        std::mem::swap(&mut self.previous, &mut self.indices);
+        self.previous_size = (width, height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4x4 cells of 2x6 pixels, so frames of 8x24 pixels are drawn one to one.
+    const TERM_SIZE: TermSize = TermSize {
+        width_cells: 4,
+        // One more, for the row left free at the bottom.
+        height_cells: 5,
+        width_of_a_cell_in_pixels: 2,
+        height_of_a_cell_in_pixels: 6,
+        font_ratio: 2.0 / 6.0,
+    };
+
+    fn render(renderer: &mut SixelRenderer, pixels: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        renderer.render(&Frame { pixels, width: 8, height: 24 }, &TERM_SIZE, &mut out);
+        out
+    }
+
+    fn count(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack.windows(needle.len()).filter(|window| *window == needle).count()
+    }
+
+    /// Black on the left half, white on the right.
+    fn two_colors() -> Vec<u8> {
+        (0..8 * 24).flat_map(|i| if i % 8 < 4 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }).collect()
+    }
+
+    #[test]
+    fn writes_repeats_of_more_than_three_as_runs() {
+        let mut out = Vec::new();
+        write_run_length(&mut out, &[0, 0, 0, 0, 0, 1, 1, 1, 2]);
+        assert_eq!(out, b"!5?@@@A");
+
+        out.clear();
+        write_run_length(&mut out, &[]);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_palette_for_similar_frames() {
+        let mut renderer = SixelRenderer::new(Redraw::Full);
+        let first = two_colors();
+        render(&mut renderer, &first);
+        let colors = renderer.palette.as_ref().unwrap().colors.clone();
+
+        // Black turns a shade lighter, which is still close enough on average.
+        let similar: Vec<u8> = first.iter().map(|&v| if v == 255 { v } else { v + 8 }).collect();
+        render(&mut renderer, &similar);
+        assert_eq!(renderer.palette.as_ref().unwrap().colors, colors);
+
+        let red: Vec<u8> = (0..8 * 24).flat_map(|_| [255, 0, 0, 255]).collect();
+        render(&mut renderer, &red);
+        assert_ne!(renderer.palette.as_ref().unwrap().colors, colors);
+    }
+
+    #[test]
+    fn damage_redraws_only_the_changed_rows_of_cells() {
+        let mut renderer = SixelRenderer::new(Redraw::Damage);
+        let mut pixels = two_colors();
+        let out = render(&mut renderer, &pixels);
+        assert!(out.starts_with(b"\x1b[H"));
+        assert_eq!(count(&out, b"\x1bP"), 1);
+
+        assert!(render(&mut renderer, &pixels).is_empty());
+
+        // A pixel in the third row of cells.
+        pixels[(13 * 8) * 4..(13 * 8) * 4 + 3].fill(255);
+        let out = render(&mut renderer, &pixels);
+        assert!(out.starts_with(b"\x1b[3;1H"));
+        assert_eq!(count(&out, b"\x1bP"), 1);
+        // Just the six pixel rows of that cell row.
+        assert_eq!(count(&out, b"\"1;1;8;6"), 1);
+    }
+
+    #[test]
+    fn leaves_a_row_free_when_the_size_is_unknown() {
+        let mut renderer = SixelRenderer::new(Redraw::Full);
+        let term_size = TermSize { width_cells: -1, height_cells: -1, ..TERM_SIZE };
+        let mut out = Vec::new();
+        renderer.render(&Frame { pixels: &two_colors(), width: 8, height: 24 }, &term_size, &mut out);
+        // A square frame in 23 of the 24 fallback rows.
+        assert_eq!(count(&out, b"\"1;1;46;138"), 1);
+    }
+}
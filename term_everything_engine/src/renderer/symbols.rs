@@ -0,0 +1,304 @@
+use std::io::Write;
+
+use crate::renderer::{fit_cells, Frame, Renderer};
+use term_size::TermSize;
+
+/// The block or dot characters a cell is drawn with. Each splits a cell
+/// into a grid of sub-pixels that are either the foreground or background color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolSet {
+    /// ▀ — 1x2 sub-pixels.
+    HalfBlock,
+    /// ▚ — 2x2 sub-pixels.
+    Quadrant,
+    /// 🬗 — 2x3 sub-pixels. Needs a font with Unicode 13's legacy computing symbols.
+    Sextant,
+    /// ⣿ — 2x4 sub-pixels.
+    Braille,
+}
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// The braille dot of each sub-pixel, in row-major order.
+const BRAILLE_DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+impl SymbolSet {
+    /// Sub-pixels across and down a cell.
+    fn grid(self) -> (usize, usize) {
+        match self {
+            SymbolSet::HalfBlock => (1, 2),
+            SymbolSet::Quadrant => (2, 2),
+            SymbolSet::Sextant => (2, 3),
+            SymbolSet::Braille => (2, 4),
+        }
+    }
+
+    /// The character with the foreground at the set bits of `mask`.
+    /// Bit n is sub-pixel n, counting across then down from the top left.
+    fn symbol(self, mask: u32) -> char {
+        match self {
+            SymbolSet::HalfBlock => [' ', '▀', '▄', '█'][mask as usize],
+            SymbolSet::Quadrant => QUADRANTS[mask as usize],
+            SymbolSet::Sextant => match mask {
+                0 => ' ',
+                0b010101 => '▌',
+                0b101010 => '▐',
+                0b111111 => '█',
+                // U+1FB00 onwards has the other 60, in order, without the two halves.
+                _ => {
+                    let skipped = (mask > 0b010101) as u32 + (mask > 0b101010) as u32;
+                    char::from_u32(0x1FB00 + mask - 1 - skipped).unwrap()
+                }
+            },
+            SymbolSet::Braille if mask == 0 => ' ',
+            SymbolSet::Braille => {
+                let dots = (0..8).filter(|bit| mask & (1 << bit) != 0).map(|bit| BRAILLE_DOTS[bit]).sum::<u32>();
+                char::from_u32(0x2800 + dots).unwrap()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    TrueColor,
+    /// The xterm 256 color palette, without the 16 the terminal theme changes.
+    Indexed256,
+}
+
+type Rgb = [u8; 3];
+
+/// The nearest color of the 6x6x6 cube or the gray ramp of the xterm palette.
+fn to_indexed(color: Rgb) -> u8 {
+    const LEVELS: [i32; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v as usize - 35) / 40,
+    };
+    let distance = |a: [i32; 3]| (0..3).map(|c| (a[c] - color[c] as i32).pow(2)).sum::<i32>();
+
+    let cube = color.map(level);
+    let cube_color = cube.map(|l| LEVELS[l]);
+    let average = color.iter().map(|&c| c as i32).sum::<i32>() / 3;
+    let gray = ((average - 8).max(0) / 10).min(23);
+    let gray_level = 8 + gray * 10;
+    if distance([gray_level; 3]) < distance(cube_color) {
+        232 + gray as u8
+    } else {
+        16 + (36 * cube[0] + 6 * cube[1] + cube[2]) as u8
+    }
+}
+
+/// A pure-Rust stand-in for chafa's symbol mode: every cell gets the symbol,
+/// foreground and background that best match its pixels.
+pub struct SymbolRenderer {
+    symbols: SymbolSet,
+    colors: ColorMode,
+}
+
+impl SymbolRenderer {
+    pub fn new(symbols: SymbolSet, colors: ColorMode) -> Self {
+        Self { symbols, colors }
+    }
+
+    /// Reads $TERM_EVERYTHING_SYMBOLS (HALF, QUAD, SEXTANT or BRAILLE) and
+    /// $TERM_EVERYTHING_CANVAS_MODE (TRUECOLOR or INDEXED_256), like the chafa path.
+    /// Without them: half blocks, and truecolor if $COLORTERM says the terminal has it.
+    pub fn from_env() -> Self {
+        let symbols = match std::env::var("TERM_EVERYTHING_SYMBOLS").as_deref() {
+            Ok("QUAD") => SymbolSet::Quadrant,
+            Ok("SEXTANT") => SymbolSet::Sextant,
+            Ok("BRAILLE") => SymbolSet::Braille,
+            _ => SymbolSet::HalfBlock,
+        };
+        let colors = match std::env::var("TERM_EVERYTHING_CANVAS_MODE").as_deref() {
+            Ok("TRUECOLOR") => ColorMode::TrueColor,
+            Ok("INDEXED_256") | Ok("INDEXED_240") => ColorMode::Indexed256,
+            _ => match std::env::var("COLORTERM").as_deref() {
+                Ok("truecolor") | Ok("24bit") => ColorMode::TrueColor,
+                _ => ColorMode::Indexed256,
+            },
+        };
+        Self::new(symbols, colors)
+    }
+
+    /// Draws the frame scaled to exactly `width_cells` by `height_cells`,
+    /// starting at the top left of the terminal.
+    pub fn render_cells(&self, frame: &Frame, width_cells: usize, height_cells: usize, out: &mut Vec<u8>) {
+        let (grid_width, grid_height) = self.symbols.grid();
+        // Sub-pixel columns and rows across the whole canvas.
+        let columns = width_cells * grid_width;
+        let rows = height_cells * grid_height;
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        if columns == 0 || rows == 0 || width == 0 || height == 0 {
+            return;
+        }
+        // The frame pixels each sub-pixel covers, at least one.
+        let span = |index: usize, count: usize, size: usize| {
+            let start = index * size / count;
+            start..((index + 1) * size / count).max(start + 1)
+        };
+
+        let mut last: (Option<Rgb>, Option<Rgb>) = (None, None);
+        let mut colors = vec![[0u32; 3]; grid_width * grid_height];
+        for cell_y in 0..height_cells {
+            write!(out, "\x1b[{};1H", cell_y + 1).unwrap();
+            for cell_x in 0..width_cells {
+                // Box-filter the frame into the cell's sub-pixels.
+                for (index, color) in colors.iter_mut().enumerate() {
+                    let xs = span(cell_x * grid_width + index % grid_width, columns, width);
+                    let ys = span(cell_y * grid_height + index / grid_width, rows, height);
+                    let mut sum = [0u32; 3];
+                    for y in ys.clone() {
+                        for pixel in frame.pixels[(y * width + xs.start) * 4..(y * width + xs.end) * 4].chunks_exact(4) {
+                            for c in 0..3 {
+                                sum[c] += pixel[c] as u32;
+                            }
+                        }
+                    }
+                    let count = (xs.len() * ys.len()) as u32;
+                    *color = sum.map(|s| s / count);
+                }
+
+                let (mask, foreground, background) = split_colors(&colors);
+                let symbol = self.symbols.symbol(mask);
+                // Spaces do not show their foreground, so it can stay whatever it was.
+                let foreground = (symbol != ' ').then_some(foreground);
+                self.write_colors(out, &mut last, foreground, background);
+
+                let mut utf8 = [0; 4];
+                out.extend_from_slice(symbol.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+        out.extend_from_slice(b"\x1b[0m");
+    }
+
+    /// Sets the colors that changed since the last cell, in one sequence.
+    fn write_colors(&self, out: &mut Vec<u8>, last: &mut (Option<Rgb>, Option<Rgb>), foreground: Option<Rgb>, background: Rgb) {
+        let mut params = Vec::new();
+        if let Some(foreground) = foreground.filter(|&color| last.0 != Some(color)) {
+            params.push(self.color_param(38, foreground));
+            last.0 = Some(foreground);
+        }
+        if last.1 != Some(background) {
+            params.push(self.color_param(48, background));
+            last.1 = Some(background);
+        }
+        if !params.is_empty() {
+            write!(out, "\x1b[{}m", params.join(";")).unwrap();
+        }
+    }
+
+    fn color_param(&self, kind: u8, color: Rgb) -> String {
+        match self.colors {
+            ColorMode::TrueColor => format!("{};2;{};{};{}", kind, color[0], color[1], color[2]),
+            ColorMode::Indexed256 => format!("{};5;{}", kind, to_indexed(color)),
+        }
+    }
+}
+
+/// Splits a cell's sub-pixels in two along the color channel that varies the
+/// most. Returns which are in the brighter half, and the average color of
+/// each half. A cell of one color is all background, otherwise both halves
+/// have sub-pixels.
+fn split_colors(colors: &[[u32; 3]]) -> (u32, Rgb, Rgb) {
+    let channel_range = |c: usize| {
+        let values = colors.iter().map(|color| color[c]);
+        (values.clone().min().unwrap(), values.max().unwrap())
+    };
+    let (channel, (min, max)) = (0..3)
+        .map(|c| (c, channel_range(c)))
+        .max_by_key(|(_, (min, max))| max - min)
+        .unwrap();
+    let threshold = (min + max) / 2;
+
+    let mut mask = 0;
+    let mut sums = [[0u32; 3]; 2];
+    let mut counts = [0u32; 2];
+    for (index, color) in colors.iter().enumerate() {
+        let half = (max > min && color[channel] > threshold) as usize;
+        mask |= (half as u32) << index;
+        counts[half] += 1;
+        for c in 0..3 {
+            sums[half][c] += color[c];
+        }
+    }
+    let average = |half: usize| sums[half].map(|sum| (sum / counts[half].max(1)) as u8);
+    (mask, average(1), average(0))
+}
+
+impl Renderer for SymbolRenderer {
+    fn render(&mut self, frame: &Frame, term_size: &TermSize, out: &mut Vec<u8>) {
+        let (width_cells, height_cells) = fit_cells(frame, term_size);
+        self.render_cells(frame, width_cells, height_cells, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sextants_skip_the_half_blocks() {
+        let symbol = |mask| SymbolSet::Sextant.symbol(mask);
+        assert_eq!(symbol(0b000001), '\u{1FB00}');
+        assert_eq!(symbol(0b010100), '\u{1FB13}');
+        assert_eq!(symbol(0b010101), '▌');
+        assert_eq!(symbol(0b010110), '\u{1FB14}');
+        assert_eq!(symbol(0b101010), '▐');
+        assert_eq!(symbol(0b111110), '\u{1FB3B}');
+        assert_eq!(symbol(0b111111), '█');
+
+        // The other 60 use every character of the range, in order.
+        let others: Vec<char> = (1..0b111111).filter(|&mask| mask != 0b010101 && mask != 0b101010).map(symbol).collect();
+        assert_eq!(others.len(), 60);
+        assert!(others.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn braille_dots_follow_the_sub_pixels() {
+        let symbol = |mask| SymbolSet::Braille.symbol(mask);
+        assert_eq!(symbol(0), ' ');
+        // Dot 1 is the top left, dot 4 the top right, dots 7 and 8 the bottom row.
+        assert_eq!(symbol(0b00000001), '⠁');
+        assert_eq!(symbol(0b00000010), '⠈');
+        assert_eq!(symbol(0b00000100), '⠂');
+        assert_eq!(symbol(0b01000000), '⡀');
+        assert_eq!(symbol(0b10000000), '⢀');
+        assert_eq!(symbol(0b11111111), '⣿');
+    }
+
+    #[test]
+    fn half_blocks_and_quadrants() {
+        assert_eq!(SymbolSet::HalfBlock.symbol(0b01), '▀');
+        assert_eq!(SymbolSet::HalfBlock.symbol(0b10), '▄');
+        assert_eq!(SymbolSet::Quadrant.symbol(0b1001), '▚');
+        assert_eq!(SymbolSet::Quadrant.symbol(0b0110), '▞');
+    }
+
+    #[test]
+    fn splits_along_the_channel_that_varies_most() {
+        let colors = [[200, 10, 0], [0, 20, 0], [0, 30, 0], [200, 40, 0]];
+        assert_eq!(split_colors(&colors), (0b1001, [200, 25, 0], [0, 25, 0]));
+    }
+
+    #[test]
+    fn one_color_is_all_background() {
+        let (mask, _, background) = split_colors(&[[10, 20, 30]; 4]);
+        assert_eq!(mask, 0);
+        assert_eq!(background, [10, 20, 30]);
+    }
+
+    #[test]
+    fn picks_the_nearest_palette_color() {
+        assert_eq!(to_indexed([0, 0, 0]), 16);
+        assert_eq!(to_indexed([255, 255, 255]), 231);
+        assert_eq!(to_indexed([255, 0, 0]), 196);
+        assert_eq!(to_indexed([95, 135, 175]), 67);
+        // Grays are closer on the gray ramp than in the cube.
+        assert_eq!(to_indexed([128, 128, 128]), 244);
+    }
+}
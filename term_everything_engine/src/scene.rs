@@ -0,0 +1,81 @@
+use crate::protocols::wl_pointer::WlPointer;
+use crate::protocols::wl_surface::{SurfaceRole, WlSurface};
+use crate::protocols::xdg_popup::XdgPopup;
+use crate::wayland_client::WaylandClient;
+
+/// A mapped surface and where it goes on the virtual monitor.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneSurface {
+    pub surface_id: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A toplevel with its subsurfaces and popups, bottom to top.
+#[derive(Debug)]
+pub struct Window {
+    /// Windows are stacked in the order they were mapped.
+    pub map_order: u64,
+    pub surfaces: Vec<SceneSurface>,
+}
+
+/// Subsurfaces of subsurfaces of popups... more than this is a loop or abuse.
+const MAX_DEPTH: usize = 32;
+
+/// The mapped toplevels of a client. Toplevels are placed at the top left of the monitor.
+pub fn windows(client: &WaylandClient) -> Vec<Window> {
+    let mut windows = Vec::new();
+    for (surface_id, surface) in client.objects::<WlSurface>() {
+        // A destroyed xdg_toplevel leaves the role behind, but the surface is unmapped.
+        let (Some(SurfaceRole::XdgToplevel), Some(_), Some(map_order)) = (surface.role(), surface.role_object_id(), surface.map_order()) else {
+            continue;
+        };
+        let mut surfaces = Vec::new();
+        add_tree(client, surface_id, 0, 0, 0, &mut surfaces);
+        windows.push(Window { map_order, surfaces });
+    }
+    windows
+}
+
+/// The client's cursor, if it set one, it is mapped and the pointer's position is known.
+/// It goes above all windows.
+pub fn cursor(client: &WaylandClient) -> Option<SceneSurface> {
+    client.objects::<WlPointer>().find_map(|(_, pointer)| {
+        let (surface_id, x, y) = pointer.cursor(client.get_id())?;
+        client.get_object::<WlSurface>(surface_id)?.texture()?;
+        Some(SceneSurface { surface_id, x, y })
+    })
+}
+
+/// Adds a surface, its subsurfaces in stacking order, then its popups.
+/// Surfaces without a texture are unmapped, and so is everything on them.
+fn add_tree(client: &WaylandClient, surface_id: u32, x: i32, y: i32, depth: usize, out: &mut Vec<SceneSurface>) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Some(surface) = client.get_object::<WlSurface>(surface_id) else {
+        return;
+    };
+    if surface.texture().is_none() {
+        return;
+    }
+
+    for entry in surface.stack() {
+        if entry.surface_id == surface_id {
+            out.push(SceneSurface { surface_id, x, y });
+        } else {
+            add_tree(client, entry.surface_id, x + entry.x, y + entry.y, depth + 1, out);
+        }
+    }
+
+    // Popups open on top of their parent, older ones first.
+    let mut popups: Vec<(u32, &XdgPopup)> = client
+        .objects::<XdgPopup>()
+        .filter(|(_, popup)| popup.parent_surface_id() == Some(surface_id))
+        .collect();
+    popups.sort_by_key(|(popup_id, _)| *popup_id);
+    for (_, popup) in popups {
+        let (popup_x, popup_y) = popup.position();
+        add_tree(client, popup.surface_id(), x + popup_x, y + popup_y, depth + 1, out);
+    }
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+
+/// Writes bytes to a client socket, attaching `fds` as SCM_RIGHTS
+/// ancillary data. The descriptors are duplicated into the client by the
+/// kernel, so the caller may close its copies once this returns.
+/// This is the Rust equivalent of Send_Message_And_File_Descriptors.cpp.
+///
+/// Returns the number of bytes written, which may be less than `buf.len()`.
+pub fn send_message_and_file_descriptors(
+    client_socket: RawFd,
+    buf: &[u8],
+    fds: &[OwnedFd],
+) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    let fds_len = mem::size_of_val(fds);
+    let cmsg_space = unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize;
+    // u64 keeps the control buffer aligned for cmsghdr.
+    let mut cmsg_buf = vec![0u64; cmsg_space.div_ceil(mem::size_of::<u64>())];
+    if !fds.is_empty() {
+        msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+        msg.msg_controllen = cmsg_space as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+            let data = libc::CMSG_DATA(cmsg).cast::<libc::c_int>();
+            for (i, fd) in fds.iter().enumerate() {
+                data.add(i).write_unaligned(fd.as_raw_fd());
+            }
+        }
+    }
+
+    // MSG_NOSIGNAL: a client that went away must not kill us with SIGPIPE.
+    let n = unsafe { libc::sendmsg(client_socket, &msg, libc::MSG_NOSIGNAL) };
+    if n == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
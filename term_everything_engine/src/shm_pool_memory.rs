@@ -0,0 +1,209 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::ptr;
+use std::sync::{Once, OnceLock};
+
+/// A client's wl_shm_pool memory, mapped read-only into the compositor.
+/// This is the Rust equivalent of SHM_Pool_Memory in c_interop.
+///
+/// The mapping lives as long as the pool or any buffer created from it.
+pub struct ShmPoolMemory {
+    addr: *mut libc::c_void,
+    size: usize,
+}
+
+// The mapping is only accessed through &self/&mut self, like any owned buffer.
+unsafe impl Send for ShmPoolMemory {}
+
+impl ShmPoolMemory {
+    /// The fd can be closed afterwards; the mapping keeps the memory alive,
+    /// and mremap grows it without the fd.
+    pub fn new(fd: &OwnedFd, size: usize) -> io::Result<Self> {
+        install_sigbus_handler();
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { addr, size })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Grows the mapping to `new_size`. The contents may move.
+    pub fn grow(&mut self, new_size: usize) -> io::Result<()> {
+        if new_size <= self.size {
+            return Ok(());
+        }
+        let addr = unsafe { libc::mremap(self.addr, self.size, new_size, libc::MREMAP_MAYMOVE) };
+        if addr == libc::MAP_FAILED {
+            // The old mapping is left as it was.
+            return Err(io::Error::last_os_error());
+        }
+        self.addr = addr;
+        self.size = new_size;
+        Ok(())
+    }
+
+    /// Calls `f` with the bytes in `offset..offset + len`.
+    ///
+    /// The client can truncate the file behind the pool at any time, which
+    /// makes reading the mapping raise SIGBUS. While `f` runs, that is caught:
+    /// the pool is replaced with zeroes and `Truncated` is returned.
+    pub fn read<R>(&self, offset: usize, len: usize, f: impl FnOnce(&[u8]) -> R) -> Result<R, ShmReadError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => {}
+            _ => return Err(ShmReadError::OutOfBounds),
+        }
+        let bytes = unsafe { std::slice::from_raw_parts((self.addr as *const u8).add(offset), len) };
+
+        CURRENT_ACCESS.with(|access| {
+            assert!(access.get().is_none(), "nested shm pool access");
+            access.set(Some(Access { addr: self.addr as usize, size: self.size, truncated: false }));
+        });
+        // Ends the access even if `f` panics.
+        struct EndAccess;
+        impl Drop for EndAccess {
+            fn drop(&mut self) {
+                CURRENT_ACCESS.with(|access| access.set(None));
+            }
+        }
+        let end_access = EndAccess;
+        let result = f(bytes);
+        let truncated = CURRENT_ACCESS.with(|access| access.get().is_some_and(|access| access.truncated));
+        drop(end_access);
+
+        if truncated {
+            return Err(ShmReadError::Truncated);
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmReadError {
+    /// The range is not inside the pool.
+    OutOfBounds,
+    /// The client shrank the file behind the pool.
+    Truncated,
+}
+
+impl fmt::Display for ShmReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShmReadError::OutOfBounds => write!(f, "read outside of the shm pool"),
+            ShmReadError::Truncated => write!(f, "the shm pool file was truncated"),
+        }
+    }
+}
+
+/// The pool being read on this thread, for the SIGBUS handler.
+#[derive(Clone, Copy)]
+struct Access {
+    addr: usize,
+    size: usize,
+    truncated: bool,
+}
+
+thread_local! {
+    // const-initialized and without a destructor, so it is safe to use from a signal handler.
+    static CURRENT_ACCESS: Cell<Option<Access>> = const { Cell::new(None) };
+}
+
+struct SigAction(libc::sigaction);
+// Only read after it is set, and it is plain data.
+unsafe impl Sync for SigAction {}
+unsafe impl Send for SigAction {}
+
+static PREVIOUS_SIGBUS_ACTION: OnceLock<SigAction> = OnceLock::new();
+
+/// Like libwayland's wl_shm_buffer_begin_access: a fault inside the pool
+/// being read is fixed up by mapping zeroes over it, anything else is passed on.
+fn install_sigbus_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigbus as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGBUS, &action, &mut previous) == -1 {
+            eprintln!("[ShmPoolMemory] Failed to install SIGBUS handler: {}", io::Error::last_os_error());
+            return;
+        }
+        let _ = PREVIOUS_SIGBUS_ACTION.set(SigAction(previous));
+    });
+}
+
+extern "C" fn handle_sigbus(signum: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let fault_addr = unsafe { (*info).si_addr() } as usize;
+
+    let fixed = CURRENT_ACCESS.with(|access| {
+        let Some(mut current) = access.get() else {
+            return false;
+        };
+        if fault_addr < current.addr || fault_addr >= current.addr + current.size {
+            return false;
+        }
+        // Replace the whole pool, the read restarts on zeroes.
+        let addr = unsafe {
+            libc::mmap(
+                current.addr as *mut libc::c_void,
+                current.size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_FIXED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return false;
+        }
+        current.truncated = true;
+        access.set(Some(current));
+        true
+    });
+    if fixed {
+        return;
+    }
+
+    // Not ours: hand it to whoever had SIGBUS before us, or crash as usual.
+    match PREVIOUS_SIGBUS_ACTION.get() {
+        Some(SigAction(previous)) if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+            let previous: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                unsafe { std::mem::transmute(previous.sa_sigaction) };
+            previous(signum, info, context);
+        }
+        Some(SigAction(previous))
+            if previous.sa_sigaction != libc::SIG_DFL && previous.sa_sigaction != libc::SIG_IGN =>
+        {
+            let previous: extern "C" fn(libc::c_int) = unsafe { std::mem::transmute(previous.sa_sigaction) };
+            previous(signum);
+        }
+        _ => unsafe {
+            // Returning re-runs the faulting read, which now gets the default action.
+            libc::signal(libc::SIGBUS, libc::SIG_DFL);
+        },
+    }
+}
+
+impl Drop for ShmPoolMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.addr, self.size);
+        }
+    }
+}
@@ -1,20 +1,31 @@
-use std::time::Duration;
+use std::io::Write;
+use std::time::{Duration, Instant};
 use tokio::time;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::blend::{blend_rect, BlendMode};
+use crate::global_registry::{self, GlobalRegistry};
+use crate::protocols::wl_surface::{Texture, WlSurface};
+use crate::region::Rect;
+use crate::renderer::{Frame, Renderer};
+use crate::scene::{self, Window};
+use term_size::TermSize;
 use crate::wayland_client::WaylandClient;
 
 // A conceptual representation of the application's shared state.
 // In a real app, this would be much more complex.
 pub struct AppState {
     pub clients: Vec<Arc<Mutex<WaylandClient>>>,
+    pub globals: Arc<Mutex<GlobalRegistry>>,
 }
 
-// A conceptual helper for compositing surfaces.
-// In a real app, this would use a 2D graphics library like `skia-safe` or `raqote`.
+/// The software compositor: draws every client's windows and cursor into
+/// one RGBA buffer the size of the virtual monitor.
+/// This is the Rust equivalent of Canvas_Desktop.ts.
 struct CanvasDesktop {
     width: u32,
     height: u32,
     buffer: Vec<u8>,
+    blend_mode: BlendMode,
 }
 impl CanvasDesktop {
     fn new(width: u32, height: u32) -> Self {
@@ -22,36 +33,67 @@ impl CanvasDesktop {
             width,
             height,
             buffer: vec![0; (width * height * 4) as usize], // RGBA buffer
+            blend_mode: BlendMode::from_env(),
         }
     }
-    // This is the core compositing logic.
-    fn draw_clients(&mut self, _app_state: &AppState) {
-        // 1. Clear the canvas (e.g., to black)
-        self.buffer.fill(0);
-
-        // 2. Gather all surfaces from all clients that should be drawn.
-        // let mut surfaces_to_draw = Vec::new();
-        // for client_arc in &app_state.clients {
-        //     let client = client_arc.lock().unwrap();
-        //     // Get surfaces, their positions, z-order, etc.
-        //     // surfaces_to_draw.push(...);
-        // }
-
-        // 3. Sort surfaces by z-order.
-        // surfaces_to_draw.sort_by_key(|s| s.z_order);
-
-        // 4. Iterate and "paint" each surface onto the main buffer.
-        // for surface in surfaces_to_draw {
-        //     // Get the surface's texture (pixel data)
-        //     // let texture = surface.get_texture();
-        //     // Get the surface's position (x, y)
-        //     // let pos = surface.get_position();
-        //
-        //     // Blit the texture onto self.buffer at the correct position.
-        //     // This is a complex operation involving copying pixel data row by row.
-        // }
-        println!("[CanvasDesktop] Drawing all client surfaces to main buffer (stub).");
+
+    /// Composites the whole scene, bottom to top: every window in the order
+    /// they were mapped, then the cursors.
+    fn draw_clients(&mut self, app_state: &AppState) {
+        // Every client is locked for the whole frame, so no surface changes halfway.
+        let clients: Vec<MutexGuard<WaylandClient>> =
+            app_state.clients.iter().map(|client| client.lock().unwrap()).collect();
+
+        let mut windows: Vec<(usize, Window)> = Vec::new();
+        for (index, client) in clients.iter().enumerate() {
+            windows.extend(scene::windows(client).into_iter().map(|window| (index, window)));
+        }
+        windows.sort_by_key(|(_, window)| window.map_order);
+        let cursors = clients
+            .iter()
+            .enumerate()
+            .filter_map(|(index, client)| Some((index, scene::cursor(client)?)));
+
+        // Opaque black, behind everything.
+        for pixel in self.buffer.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0, 0, 0, 255]);
+        }
+        let surfaces = windows
+            .iter()
+            .flat_map(|(index, window)| window.surfaces.iter().map(move |surface| (*index, *surface)))
+            .chain(cursors);
+        for (index, surface) in surfaces {
+            let Some(texture) = clients[index]
+                .get_object::<WlSurface>(surface.surface_id)
+                .and_then(|s| s.texture())
+            else {
+                continue;
+            };
+            self.blend_texture(texture, surface.x, surface.y);
+        }
     }
+
+    /// Blends a texture over the canvas with its top left at (x, y),
+    /// clipped to the canvas.
+    fn blend_texture(&mut self, texture: &Texture, x: i32, y: i32) {
+        let canvas = Rect::new(0, 0, self.width as i32, self.height as i32);
+        let Some(visible) = canvas.intersect(&Rect::new(x, y, texture.width as i32, texture.height as i32)) else {
+            return;
+        };
+        let (src_x, src_y) = ((visible.x - x) as usize, (visible.y - y) as usize);
+        let src_start = (src_y * texture.width as usize + src_x) * 4;
+        let dst_start = (visible.y as usize * self.width as usize + visible.x as usize) * 4;
+        blend_rect(
+            self.blend_mode,
+            &texture.pixels[src_start..],
+            texture.width as usize * 4,
+            &mut self.buffer[dst_start..],
+            self.width as usize * 4,
+            visible.width as usize,
+            visible.height as usize,
+        );
+    }
+
     fn to_buffer(&self) -> &Vec<u8> { &self.buffer }
 }
 
@@ -59,11 +101,28 @@ impl CanvasDesktop {
 pub struct TerminalWindow {
     app_state: Arc<Mutex<AppState>>,
     canvas_desktop: CanvasDesktop,
+    // The base of the frame callback timestamps.
+    start_time: Instant,
+    // To redraw when a client connects or disconnects.
+    last_client_count: usize,
+    // None without a built-in renderer. The chafa path in rust_interop is
+    // only reachable from the Node side, so nothing is drawn then.
+    renderer: Option<Box<dyn Renderer>>,
+    // Reused between frames.
+    output: Vec<u8>,
+    // Whether the terminal shows the last composited frame.
+    shown: bool,
+    // Committed callbacks waiting for their frame to be on the terminal.
+    frame_callbacks: FrameCallbacks,
 }
 
+/// Frame callbacks of one client, taken before drawing a frame.
+type FrameCallbacks = Vec<(Arc<Mutex<WaylandClient>>, Vec<u32>)>;
+
 impl TerminalWindow {
-    pub fn new(app_state: Arc<Mutex<AppState>>) -> Self {
-        println!("[TerminalWindow] new: initializing terminal state (stub)");
+    /// `renderer` is from `renderer::from_env`, which may block on the terminal.
+    pub fn new(app_state: Arc<Mutex<AppState>>, renderer: Option<Box<dyn Renderer>>) -> Self {
+        eprintln!("[TerminalWindow] new: initializing terminal state (stub)");
 
         let virtual_width = 800;
         let virtual_height = 600;
@@ -71,6 +130,12 @@ impl TerminalWindow {
         Self {
             app_state,
             canvas_desktop: CanvasDesktop::new(virtual_width, virtual_height),
+            start_time: Instant::now(),
+            last_client_count: 0,
+            renderer,
+            output: Vec::new(),
+            shown: false,
+            frame_callbacks: Vec::new(),
         }
     }
 
@@ -80,38 +145,82 @@ impl TerminalWindow {
 
         loop {
             interval.tick().await;
+            self.update_globals();
 
-            self.fire_frame_callbacks();
-            let desktop_buffer = self.composite_scene();
-            self.draw_to_terminal(desktop_buffer);
+            // The callbacks committed so far belong to this frame. Clients
+            // are only told once it is actually on the terminal.
+            let frame_callbacks = self.take_frame_callbacks();
+            self.frame_callbacks.extend(frame_callbacks);
+            // Nothing changed, the terminal already shows this frame if it showed the last one.
+            if self.take_damage() {
+                self.composite_scene();
+                self.shown = self.draw_to_terminal();
+            }
+            if self.shown {
+                let frame_callbacks = std::mem::take(&mut self.frame_callbacks);
+                self.fire_frame_callbacks(frame_callbacks);
+            }
         }
     }
 
-    fn fire_frame_callbacks(&mut self) {
-        println!("[TerminalWindow] Firing frame callbacks for all clients (stub)");
+    fn update_globals(&self) {
+        let state = self.app_state.lock().unwrap();
+        global_registry::update_globals(&state.globals, &state.clients);
+    }
+
+    fn take_frame_callbacks(&self) -> FrameCallbacks {
         let state = self.app_state.lock().unwrap();
-        // for client_arc in &state.clients {
-        //     let mut client = client_arc.lock().unwrap();
-        //     client.fire_frame_callbacks();
-        // }
+        state
+            .clients
+            .iter()
+            .map(|client| (Arc::clone(client), client.lock().unwrap().take_frame_callbacks()))
+            .filter(|(_, callback_ids)| !callback_ids.is_empty())
+            .collect()
     }
 
-    fn composite_scene(&mut self) -> &Vec<u8> {
-        println!("[TerminalWindow] Compositing scene.");
+    /// Whether the scene changed since the last frame.
+    fn take_damage(&mut self) -> bool {
+        let state = self.app_state.lock().unwrap();
+        let mut damaged = state.clients.len() != self.last_client_count;
+        self.last_client_count = state.clients.len();
+        for client in &state.clients {
+            // Every client's damage has to be taken, so no short-circuiting.
+            damaged |= client.lock().unwrap().take_damage();
+        }
+        damaged
+    }
+
+    fn fire_frame_callbacks(&self, frame_callbacks: FrameCallbacks) {
+        // Milliseconds with an undefined base, wrapping like libwayland's.
+        let time = self.start_time.elapsed().as_millis() as u32;
+        for (client, callback_ids) in frame_callbacks {
+            client.lock().unwrap().fire_frame_callbacks(&callback_ids, time);
+        }
+    }
+
+    fn composite_scene(&mut self) {
         let state = self.app_state.lock().unwrap();
         self.canvas_desktop.draw_clients(&state);
-        self.canvas_desktop.to_buffer()
     }
 
-    fn draw_to_terminal(&mut self, buffer: &[u8]) {
-        println!("[TerminalWindow] Drawing final buffer to terminal via interop (stub).");
-        // This is the final call to the native library.
-        // rust_interop::draw_desktop(
-        //     &mut self.draw_state,
-        //     buffer,
-        //     self.canvas_desktop.width,
-        //     self.canvas_desktop.height,
-        //     "Status Line Text..."
-        // );
+    /// Returns whether the frame made it to the terminal.
+    fn draw_to_terminal(&mut self) -> bool {
+        let Some(renderer) = &mut self.renderer else {
+            return false;
+        };
+        let frame = Frame {
+            pixels: self.canvas_desktop.to_buffer(),
+            width: self.canvas_desktop.width,
+            height: self.canvas_desktop.height,
+        };
+        self.output.clear();
+        renderer.render(&frame, &TermSize::new(), &mut self.output);
+
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = stdout.write_all(&self.output).and_then(|()| stdout.flush()) {
+            eprintln!("[TerminalWindow] Failed to write to the terminal: {}", e);
+            return false;
+        }
+        true
     }
 }
@@ -1,110 +1,735 @@
-use std::collections::HashMap;
-use std::os::unix::io::RawFd;
-use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex};
 
-use crate::wayland_object::{Request, WaylandObject};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::Notify;
+
+use crate::file_descriptor_claim::FileDescriptorClaim;
+use crate::get_message_and_file_descriptors::get_message_and_file_descriptors;
+use crate::message_decoder::{decode_arguments, Message, MessageDecoder};
+use crate::message_encoder::encode_event;
+use crate::send_message_and_file_descriptors::send_message_and_file_descriptors;
+use crate::wayland_object::{Event, ProtocolError, Request, WaylandObject};
+use crate::global_registry::GlobalRegistry;
+use crate::protocols::generated::{wl_display, wl_registry};
 use crate::protocols::{
-    wl_compositor::WlCompositor,
+    wl_callback::WlCallback,
     wl_display::WlDisplay,
-    wl_shm::WlShm,
-    wl_shm_pool::WlShmPool,
-    xdg_wm_base::XdgWmBase,
-    wl_surface::WlSurface,
-    wl_seat::WlSeat,
+    wl_surface::{SurfaceRole, WlSurface},
 };
-
-struct MessageDecoder;
-impl MessageDecoder {
-    fn consume(&mut self, _buffer: &[u8]) -> Vec<Request> { vec![] }
-}
+use crate::wayland_types::Argument;
 
 pub struct WaylandClient {
     id: u32,
-    pub client_socket_fd: RawFd,
+    // Closed when the client is dropped.
+    client_socket: OwnedFd,
     objects: HashMap<u32, Box<dyn WaylandObject>>,
+    // The version each object was bound or created with; see `get_object_version`.
+    object_versions: HashMap<u32, u32>,
+    globals: Arc<Mutex<GlobalRegistry>>,
+    // The client's wl_registry objects, which receive global and global_remove.
+    registries: Vec<u32>,
     message_decoder: MessageDecoder,
+    unclaimed_file_descriptors: VecDeque<OwnedFd>,
+    // Encoded events waiting to be flushed to the socket.
+    send_message_buffer: Vec<u8>,
+    send_file_descriptors: VecDeque<OwnedFd>,
+    // Wakes main_loop to flush events queued from outside of it, e.g. by the render loop.
+    events_queued: Arc<Notify>,
     next_serial: u32,
+    // Set by post_error.
+    disconnecting: bool,
+    // Something changed that surface damage doesn't cover, e.g. a surface went away.
+    needs_redraw: bool,
 }
 
-const WL_DISPLAY_ID: u32 = 1;
-const WL_COMPOSITOR_ID: u32 = 2;
-const WL_SHM_ID: u32 = 3;
-const XDG_WM_BASE_ID: u32 = 4;
-const WL_SEAT_ID: u32 = 5;
+/// The most file descriptors sent with a single sendmsg.
+/// Matches MAX_FDS_OUT in libwayland.
+const MAX_FILE_DESCRIPTORS_PER_SEND: usize = 28;
+
+/// Ids from here on are allocated by the server.
+const SERVER_ID_START: u32 = 0xff000000;
+
+/// The id of wl_display, which protocol errors of the display interface are posted on.
+pub const WL_DISPLAY_ID: u32 = 1;
 
 impl WaylandClient {
-    pub fn new(id: u32, client_socket_fd: RawFd) -> Self {
+    pub fn new(id: u32, client_socket: OwnedFd, globals: Arc<Mutex<GlobalRegistry>>) -> Self {
         let mut client = Self {
             id,
-            client_socket_fd,
+            client_socket,
             objects: HashMap::new(),
-            message_decoder: MessageDecoder,
+            object_versions: HashMap::new(),
+            globals,
+            registries: Vec::new(),
+            message_decoder: MessageDecoder::new(),
+            unclaimed_file_descriptors: VecDeque::new(),
+            send_message_buffer: Vec::new(),
+            send_file_descriptors: VecDeque::new(),
+            events_queued: Arc::new(Notify::new()),
             next_serial: 0,
+            disconnecting: false,
+            needs_redraw: false,
         };
 
-        // Instantiate and add all the global objects.
+        // wl_display is the only object that exists before the first request.
+        // Everything else is bound through wl_registry.
         client.add_object(WL_DISPLAY_ID, Box::new(WlDisplay::new()));
-        client.add_object(WL_COMPOSITOR_ID, Box::new(WlCompositor::new()));
-        client.add_object(WL_SHM_ID, Box::new(WlShm::new()));
-        client.add_object(XDG_WM_BASE_ID, Box::new(XdgWmBase::new()));
-        client.add_object(WL_SEAT_ID, Box::new(WlSeat::new()));
 
         client
     }
 
     pub fn get_id(&self) -> u32 { self.id }
 
+    pub fn client_socket_fd(&self) -> RawFd {
+        self.client_socket.as_raw_fd()
+    }
+
+    /// The id must be free; `dispatch_message` checks the ids clients pick.
     pub fn add_object(&mut self, id: u32, object: Box<dyn WaylandObject>) {
-        println!("[WaylandClient] Adding object with ID: {}", id);
-        self.objects.insert(id, object);
+        let replaced = self.objects.insert(id, object);
+        debug_assert!(replaced.is_none(), "object {} already exists", id);
+    }
+
+    /// Typed access to another object of this client. Returns `None` if the
+    /// id is unknown or the object is not a `T`.
+    pub fn get_object<T: WaylandObject>(&self, id: u32) -> Option<&T> {
+        self.objects.get(&id)?.as_any().downcast_ref::<T>()
+    }
+
+    pub fn get_object_mut<T: WaylandObject>(&mut self, id: u32) -> Option<&mut T> {
+        self.objects.get_mut(&id)?.as_any_mut().downcast_mut::<T>()
+    }
+
+    /// Every object of type `T`, with its id, in no particular order.
+    pub fn objects<T: WaylandObject>(&self) -> impl Iterator<Item = (u32, &T)> {
+        self.objects
+            .iter()
+            .filter_map(|(id, object)| Some((*id, object.as_any().downcast_ref::<T>()?)))
+    }
+
+    /// Takes the frame callbacks of every surface, as of their last commit.
+    pub fn take_frame_callbacks(&mut self) -> Vec<u32> {
+        self.objects
+            .values_mut()
+            .filter_map(|object| object.as_any_mut().downcast_mut::<WlSurface>())
+            .flat_map(|surface| surface.take_frame_callbacks())
+            .collect()
+    }
+
+    /// Sends done to frame callbacks taken with `take_frame_callbacks`.
+    /// `time` is in milliseconds.
+    pub fn fire_frame_callbacks(&mut self, callback_ids: &[u32], time: u32) {
+        for &callback_id in callback_ids {
+            // Gone if its surface was destroyed in the meantime, and the
+            // id may have been reused since.
+            if self.get_object::<WlCallback>(callback_id).is_some() {
+                WlCallback::done(self, callback_id, time);
+            }
+        }
     }
 
-    pub fn get_global(&self, id: u32) -> Option<&Box<dyn WaylandObject>> {
-        self.objects.get(&id)
+    /// Makes the next frame redraw even if no surface has damage.
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
     }
 
+    /// Whether anything of this client changed since the last call.
+    /// Clears the damage of every surface.
+    pub fn take_damage(&mut self) -> bool {
+        let mut damaged = std::mem::take(&mut self.needs_redraw);
+        for object in self.objects.values_mut() {
+            if let Some(surface) = object.as_any_mut().downcast_mut::<WlSurface>() {
+                damaged |= !surface.take_damage().is_empty();
+            }
+        }
+        damaged
+    }
+
+    pub fn globals(&self) -> Arc<Mutex<GlobalRegistry>> {
+        Arc::clone(&self.globals)
+    }
+
+    /// (name, interface, version) of every global, straight from the registry.
     pub fn list_globals(&self) -> Vec<(u32, &'static str, u32)> {
-        vec![
-            (WL_DISPLAY_ID, "wl_display", 1),
-            (WL_COMPOSITOR_ID, "wl_compositor", 4),
-            (WL_SHM_ID, "wl_shm", 1),
-            (XDG_WM_BASE_ID, "xdg_wm_base", 1),
-            (WL_SEAT_ID, "wl_seat", 7),
-        ]
+        self.globals.lock().unwrap().list()
     }
 
-    // --- Conceptual methods for object interaction ---
-    pub fn get_surface_role(&mut self, surface_id: u32) -> Option<String> {
-        self.objects.get_mut(&surface_id)
-            .and_then(|s| (s as &mut dyn Any).downcast_mut::<WlSurface>())
-            .and_then(|s| s.get_role_name())
+    /// Starts advertising globals to a new wl_registry.
+    pub fn add_registry(&mut self, registry_id: u32) {
+        self.registries.push(registry_id);
+        for (name, interface, version) in self.list_globals() {
+            eprintln!("[WaylandClient] Advertising global: {} (name {})", interface, name);
+            wl_registry::send_global(self, registry_id, name, interface, version);
+        }
     }
 
-    pub fn set_surface_role(&mut self, surface_id: u32, role_object_id: u32, role_name: &'static str) {
-         if let Some(surface) = self.objects.get_mut(&surface_id)
-            .and_then(|s| (s as &mut dyn Any).downcast_mut::<WlSurface>()) {
-            surface.set_role(role_name, role_object_id);
+    pub fn send_global(&mut self, name: u32, interface: &str, version: u32) {
+        for registry_id in self.registries.clone() {
+            wl_registry::send_global(self, registry_id, name, interface, version);
         }
     }
 
-    pub fn signal_buffer_destroyed(&mut self, pool_id: u32, buffer_id: u32) {
-        if let Some(pool) = self.objects.get_mut(&pool_id)
-            .and_then(|p| (p as &mut dyn Any).downcast_mut::<WlShmPool>()) {
-            pool.buffer_destroyed(self, buffer_id);
+    pub fn send_global_remove(&mut self, name: u32) {
+        for registry_id in self.registries.clone() {
+            wl_registry::send_global_remove(self, registry_id, name);
         }
     }
 
+    pub fn set_object_version(&mut self, id: u32, version: u32) {
+        self.object_versions.insert(id, version);
+    }
+
+    /// The version of the global an object descends from. Objects created by
+    /// a request have the version of the object the request was sent to.
+    pub fn get_object_version(&self, id: u32) -> u32 {
+        self.object_versions.get(&id).copied().unwrap_or(1)
+    }
+
+    // --- Methods for object interaction ---
+    pub fn get_surface_role(&self, surface_id: u32) -> Option<SurfaceRole> {
+        self.get_object::<WlSurface>(surface_id)
+            .and_then(|s| s.role())
+    }
+
+    /// Forgets the role object of a surface once it is destroyed.
+    pub fn clear_surface_role_object(&mut self, surface_id: u32) {
+        if let Some(surface) = self.get_object_mut::<WlSurface>(surface_id) {
+            surface.clear_role_object();
+        }
+        // The surface is unmapped.
+        self.request_redraw();
+    }
+
+    /// Returns false if the surface already has a different role or a role object, or there is no such surface.
+    pub fn set_surface_role(&mut self, surface_id: u32, role: SurfaceRole, role_object_id: Option<u32>) -> bool {
+        self.get_object_mut::<WlSurface>(surface_id).is_some_and(|surface| surface.set_role(role, role_object_id))
+    }
+
     pub fn next_serial(&mut self) -> u32 {
         self.next_serial += 1;
         self.next_serial
     }
 
-    pub fn get_client_state_ref(&self) -> u32 { 0 }
+    /// Queues an event for the client. Nothing is written until the next flush.
+    pub fn send_event(&mut self, event: Event) {
+        let mut fds = Vec::new();
+        encode_event(event, &mut self.send_message_buffer, &mut fds);
+        self.send_file_descriptors.extend(fds);
+        self.events_queued.notify_one();
+    }
+
+    pub fn has_pending_events(&self) -> bool {
+        !self.send_message_buffer.is_empty()
+    }
+
+    /// Writes as many queued events as the socket accepts.
+    /// Returns a `WouldBlock` error if the socket is full.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.send_message_buffer.is_empty() {
+            // File descriptors go out with the first bytes written after they were queued,
+            // which is never later than the message that references them.
+            let fd_count = self.send_file_descriptors.len().min(MAX_FILE_DESCRIPTORS_PER_SEND);
+            let fds: Vec<OwnedFd> = self.send_file_descriptors.drain(..fd_count).collect();
+            match send_message_and_file_descriptors(self.client_socket_fd(), &self.send_message_buffer, &fds) {
+                Ok(bytes_written) => {
+                    self.send_message_buffer.drain(..bytes_written);
+                }
+                Err(e) => {
+                    // Nothing was sent, put the descriptors back in order.
+                    for fd in fds.into_iter().rev() {
+                        self.send_file_descriptors.push_front(fd);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the bytes read from the client socket and dispatches
+    /// every complete request to the object it is addressed to.
+    /// Stops at the first protocol error, which is posted to the client.
+    pub fn parse_messages(&mut self, buffer: &[u8]) {
+        let messages = match self.message_decoder.consume(buffer) {
+            Ok(messages) => messages,
+            Err(error) => {
+                self.post_error(ProtocolError::new(WL_DISPLAY_ID, wl_display::error::INVALID_METHOD, error.to_string()));
+                return;
+            }
+        };
+        for message in messages {
+            // Nothing the client sends after an error is handled.
+            if self.disconnecting {
+                return;
+            }
+            if let Err(error) = self.dispatch_message(message) {
+                self.post_error(error);
+            }
+        }
+    }
+
+    fn dispatch_message(&mut self, message: Message) -> Result<(), ProtocolError> {
+        // Take the object out of the map while it handles the request,
+        // so that it can be given mutable access to the client.
+        let Some(mut object) = self.objects.remove(&message.object_id) else {
+            return Err(ProtocolError::new(
+                WL_DISPLAY_ID,
+                wl_display::error::INVALID_OBJECT,
+                format!("invalid object {}", message.object_id),
+            ));
+        };
+        let object_id = message.object_id;
+        let interface = object.interface();
+        let version = self.get_object_version(object_id);
+        let result = match interface.request_signature(message.opcode) {
+            // A request the object's version doesn't have.
+            Some(_) if interface.request_since(message.opcode) > version => Err(ProtocolError::new(
+                object_id,
+                wl_display::error::INVALID_METHOD,
+                format!(
+                    "{}@{}.{}: needs version {}, the object has version {}",
+                    interface.name,
+                    object_id,
+                    message.opcode,
+                    interface.request_since(message.opcode),
+                    version
+                ),
+            )),
+            Some(signature) => match decode_arguments(signature, &message.data, self) {
+                Ok(args) => 'request: {
+                    // Like libwayland, a new id has to be free and in the client's range.
+                    for arg in &args {
+                        if let Argument::NewId(id) = *arg {
+                            if id == 0 || id >= SERVER_ID_START || id == object_id || self.has_object(id) {
+                                break 'request Err(ProtocolError::new(
+                                    WL_DISPLAY_ID,
+                                    wl_display::error::INVALID_OBJECT,
+                                    format!("invalid new id {}", id),
+                                ));
+                            }
+                        }
+                    }
+                    // New objects inherit the version of their parent, unless the
+                    // handler sets one itself (wl_registry.bind). It is known before
+                    // the handler runs, which may already send version-gated events.
+                    let new_ids: Vec<u32> = args
+                        .iter()
+                        .filter_map(|arg| match arg {
+                            Argument::NewId(id) => Some(*id),
+                            _ => None,
+                        })
+                        .collect();
+                    for &id in &new_ids {
+                        self.object_versions.insert(id, version);
+                    }
+                    let result = object.on_request(self, Request { object_id, opcode: message.opcode, args });
+                    // Forget the ids that did not become objects: the request failed,
+                    // or created nothing (a bind to a removed global).
+                    for id in new_ids {
+                        if result.is_err() || !self.has_object(id) {
+                            self.object_versions.remove(&id);
+                        }
+                    }
+                    result
+                }
+                Err(error) => Err(ProtocolError::new(
+                    object_id,
+                    wl_display::error::INVALID_METHOD,
+                    format!("{}@{}.{}: {}", interface.name, object_id, message.opcode, error),
+                )),
+            },
+            None => Err(ProtocolError::new(
+                object_id,
+                wl_display::error::INVALID_METHOD,
+                format!("{}@{}: invalid opcode {}", interface.name, object_id, message.opcode),
+            )),
+        };
+        if result.is_ok() && interface.is_destructor(message.opcode) {
+            self.finish_destroy(object_id, object);
+        } else {
+            self.objects.entry(object_id).or_insert(object);
+        }
+        result
+    }
+
+    /// Removes an object that the server destroys, e.g. a wl_callback once it is done.
+    pub fn destroy_object(&mut self, id: u32) {
+        if let Some(object) = self.objects.remove(&id) {
+            self.finish_destroy(id, object);
+        }
+    }
+
+    fn finish_destroy(&mut self, id: u32, mut object: Box<dyn WaylandObject>) {
+        object.on_destroy(self, id);
+        self.object_versions.remove(&id);
+        self.send_delete_id(id);
+    }
+
+    /// Runs the cleanup of every object once the connection is gone.
+    /// No delete_id is sent, there is nobody left to receive it.
+    pub fn destroy_all_objects(&mut self) {
+        let ids: Vec<u32> = self.objects.keys().copied().collect();
+        for id in ids {
+            if let Some(mut object) = self.objects.remove(&id) {
+                object.on_destroy(self, id);
+            }
+        }
+    }
+
+    /// Tells the client it may reuse the id. Only ids the client allocated are acknowledged.
+    pub fn send_delete_id(&mut self, id: u32) {
+        if id < SERVER_ID_START {
+            wl_display::send_delete_id(self, WL_DISPLAY_ID, id);
+        }
+    }
+
+    pub fn has_object(&self, id: u32) -> bool {
+        self.objects.contains_key(&id)
+    }
+
+    /// Sends wl_display.error. The connection is closed once it has been flushed.
+    pub fn post_error(&mut self, error: ProtocolError) {
+        eprintln!("[WaylandClient] Protocol error for client {}: {}", self.id, error);
+        wl_display::send_error(self, WL_DISPLAY_ID, error.object_id, error.code, &error.message);
+        self.disconnecting = true;
+    }
+
+    /// True after a protocol error; the client is dropped once its events are flushed.
+    pub fn is_disconnecting(&self) -> bool {
+        self.disconnecting
+    }
+
+    /// Reads whatever is available on the client socket, queues the received
+    /// file descriptors and dispatches the complete requests.
+    /// Returns `Ok(false)` once the client has closed the connection.
+    fn read_messages(&mut self) -> io::Result<bool> {
+        let mut message_buffer = [0u8; 4096];
+        let mut file_descriptors = Vec::new();
+        let bytes_read = get_message_and_file_descriptors(
+            self.client_socket_fd(),
+            &mut message_buffer,
+            &mut file_descriptors,
+        )?;
+        // File descriptors always arrive with (or before) the message that uses them.
+        self.unclaimed_file_descriptors.extend(file_descriptors);
+        if bytes_read == 0 {
+            return Ok(false);
+        }
+        self.parse_messages(&message_buffer[..bytes_read]);
+        Ok(true)
+    }
+
+    /// Serves the client until it disconnects or the connection fails.
+    /// The client is shared with the render loop, so it is only locked while
+    /// handling a batch of requests or flushing, never across an await.
+    pub async fn main_loop(client: Arc<Mutex<WaylandClient>>) {
+        let (id, client_socket_fd, events_queued) = {
+            let client = client.lock().unwrap();
+            (client.id, client.client_socket_fd(), Arc::clone(&client.events_queued))
+        };
+        // The socket must be non-blocking to be driven by the tokio reactor.
+        unsafe {
+            let flags = libc::fcntl(client_socket_fd, libc::F_GETFL);
+            libc::fcntl(client_socket_fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+        // The client owns the socket and outlives this loop, so watching the raw fd is fine.
+        let socket = match AsyncFd::new(client_socket_fd) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("[WaylandClient] Failed to watch client socket {}: {}", client_socket_fd, e);
+                return;
+            }
+        };
 
-    pub async fn main_loop(&mut self) {
         loop {
-            println!("[WaylandClient] Finished one loop iteration (stub). Breaking.");
-            break;
+            let readable = tokio::select! {
+                readable = socket.readable() => readable,
+                _ = events_queued.notified() => {
+                    if let Err(e) = Self::flush_to(&client, &socket).await {
+                        eprintln!("[WaylandClient] Failed to write to client {}: {}", id, e);
+                        return;
+                    }
+                    continue;
+                }
+            };
+            let mut guard = match readable {
+                Ok(guard) => guard,
+                Err(e) => {
+                    eprintln!("[WaylandClient] Failed to wait on client socket: {}", e);
+                    return;
+                }
+            };
+            match guard.try_io(|_| client.lock().unwrap().read_messages()) {
+                Ok(Ok(true)) => {
+                    if let Err(e) = Self::flush_to(&client, &socket).await {
+                        eprintln!("[WaylandClient] Failed to write to client {}: {}", id, e);
+                        return;
+                    }
+                    if client.lock().unwrap().is_disconnecting() {
+                        eprintln!("[WaylandClient] Disconnecting client {} after a protocol error", id);
+                        return;
+                    }
+                }
+                Ok(Ok(false)) => {
+                    eprintln!("[WaylandClient] Client {} disconnected", id);
+                    return;
+                }
+                Ok(Err(e)) => {
+                    eprintln!("[WaylandClient] Failed to read from client {}: {}", id, e);
+                    return;
+                }
+                // Spurious wakeup, wait for readiness again.
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+impl WaylandClient {
+    /// Flushes all queued events, waiting for the socket to become writable as needed.
+    async fn flush_to(client: &Mutex<WaylandClient>, socket: &AsyncFd<RawFd>) -> io::Result<()> {
+        while client.lock().unwrap().has_pending_events() {
+            let mut guard = socket.writable().await?;
+            match guard.try_io(|_| client.lock().unwrap().flush()) {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            }
         }
+        Ok(())
+    }
+}
+
+impl FileDescriptorClaim for WaylandClient {
+    fn claim_file_descriptor(&mut self) -> Option<OwnedFd> {
+        self.unclaimed_file_descriptors.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    use crate::global_registry::{update_globals, Global, GlobalFilter};
+    use crate::protocols::generated::wl_seat;
+    use crate::wayland_object::RequestHandler;
+    use crate::wayland_types::{Interface, ObjectId};
+
+    /// A client whose events go nowhere, and the other end of its socket.
+    fn client(globals: GlobalRegistry) -> (WaylandClient, UnixStream) {
+        let (server, peer) = UnixStream::pair().unwrap();
+        (WaylandClient::new(1, server.into(), Arc::new(Mutex::new(globals))), peer)
+    }
+
+    fn send(client: &mut WaylandClient, object_id: u32, opcode: u16, args: Vec<Argument>) {
+        let mut bytes = Vec::new();
+        encode_event(Event::new(object_id, opcode, args), &mut bytes, &mut Vec::new());
+        client.parse_messages(&bytes);
+    }
+
+    fn bind(client: &mut WaylandClient, registry_id: u32, name: u32, interface: &str, version: u32, id: u32) {
+        let args = vec![
+            Argument::Uint(name),
+            Argument::String(Some(interface.to_string())),
+            Argument::Uint(version),
+            Argument::NewId(id),
+        ];
+        send(client, registry_id, 0, args);
+    }
+
+    /// A wl_seat that records the version of the objects it is asked to create.
+    struct VersionProbe(Arc<Mutex<Vec<u32>>>);
+
+    impl RequestHandler for VersionProbe {
+        type Request = wl_seat::Request;
+
+        fn handle_request(&mut self, client: &mut WaylandClient, object_id: ObjectId, request: wl_seat::Request) -> Result<(), ProtocolError> {
+            match request {
+                wl_seat::Request::GetPointer { id } => {
+                    self.0.lock().unwrap().push(client.get_object_version(id));
+                    client.add_object(id, Box::new(VersionProbe(Arc::clone(&self.0))));
+                    Ok(())
+                }
+                _ => Err(ProtocolError::new(object_id, wl_seat::error::MISSING_CAPABILITY, "no keyboard")),
+            }
+        }
+    }
+
+    struct VersionProbeGlobal(Arc<Mutex<Vec<u32>>>);
+
+    impl Global for VersionProbeGlobal {
+        fn interface(&self) -> &'static Interface {
+            &wl_seat::INTERFACE
+        }
+
+        fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+            client.add_object(id, Box::new(VersionProbe(Arc::clone(&self.0))));
+        }
+    }
+
+    #[test]
+    fn new_objects_have_their_version_while_being_created() {
+        let versions = Arc::new(Mutex::new(Vec::new()));
+        let mut globals = GlobalRegistry::new(GlobalFilter::default());
+        let name = globals.register(VersionProbeGlobal(Arc::clone(&versions))).unwrap();
+        let (mut client, _peer) = client(globals);
+
+        send(&mut client, WL_DISPLAY_ID, 1, vec![Argument::NewId(2)]);
+        bind(&mut client, 2, name, "wl_seat", 5, 3);
+        send(&mut client, 3, 0, vec![Argument::NewId(4)]);
+        assert_eq!(*versions.lock().unwrap(), [5]);
+        assert_eq!(client.get_object_version(4), 5);
+
+        // get_keyboard fails, and its id is not left with a version.
+        send(&mut client, 3, 1, vec![Argument::NewId(5)]);
+        assert!(client.is_disconnecting());
+        assert!(!client.object_versions.contains_key(&5));
+    }
+
+    #[test]
+    fn rejects_new_ids_that_are_taken_or_out_of_range() {
+        for id in [WL_DISPLAY_ID, 2, SERVER_ID_START] {
+            let (mut client, _peer) = client(GlobalRegistry::new(GlobalFilter::default()));
+            send(&mut client, WL_DISPLAY_ID, 1, vec![Argument::NewId(2)]);
+            send(&mut client, WL_DISPLAY_ID, 1, vec![Argument::NewId(id)]);
+            assert!(client.is_disconnecting(), "new id {}", id);
+            // The objects that were there are left alone.
+            assert!(client.get_object::<WlDisplay>(WL_DISPLAY_ID).is_some());
+            assert!(client.has_object(2));
+        }
+    }
+
+    #[test]
+    fn checks_the_version_of_requests_and_events() {
+        const RELEASE: u16 = 3;
+        for (version, accepted) in [(4, false), (5, true)] {
+            let mut globals = GlobalRegistry::new(GlobalFilter::default());
+            let name = globals.register(PluggableSeat(Arc::new(std::sync::atomic::AtomicBool::new(true)))).unwrap();
+            let (mut client, _peer) = client(globals);
+            send(&mut client, WL_DISPLAY_ID, 1, vec![Argument::NewId(2)]);
+            bind(&mut client, 2, name, "wl_seat", version, 3);
+            client.flush().unwrap();
+
+            // wl_seat.release is since version 5.
+            send(&mut client, 3, RELEASE, vec![]);
+            assert_eq!(client.is_disconnecting(), !accepted, "version {}", version);
+        }
+
+        // wl_seat.name is since version 2.
+        let (mut client, _peer) = client(GlobalRegistry::new(GlobalFilter::default()));
+        client.set_object_version(3, 1);
+        wl_seat::send_name(&mut client, 3, "seat0");
+        assert!(!client.has_pending_events());
+        client.set_object_version(3, 2);
+        wl_seat::send_name(&mut client, 3, "seat0");
+        assert!(client.has_pending_events());
+    }
+
+    /// A wl_seat that can be unplugged.
+    struct PluggableSeat(Arc<std::sync::atomic::AtomicBool>);
+
+    impl Global for PluggableSeat {
+        fn interface(&self) -> &'static Interface {
+            &wl_seat::INTERFACE
+        }
+
+        fn is_available(&self) -> bool {
+            self.0.load(std::sync::atomic::Ordering::Relaxed)
+        }
+
+        fn bind(&self, client: &mut WaylandClient, id: ObjectId, _version: u32) {
+            client.add_object(id, Box::new(crate::protocols::wl_seat::WlSeat::new()));
+        }
+    }
+
+    #[test]
+    fn ignores_binds_to_removed_globals() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let plugged = Arc::new(AtomicBool::new(true));
+        let mut globals = GlobalRegistry::new(GlobalFilter::default());
+        let name = globals.register(PluggableSeat(Arc::clone(&plugged))).unwrap();
+        let (client, _peer) = client(globals);
+        let globals = client.globals();
+        let client = Arc::new(Mutex::new(client));
+        send(&mut client.lock().unwrap(), WL_DISPLAY_ID, 1, vec![Argument::NewId(2)]);
+
+        plugged.store(false, Ordering::Relaxed);
+        update_globals(&globals, std::slice::from_ref(&client));
+        let mut client = client.lock().unwrap();
+        assert!(client.list_globals().is_empty());
+
+        // The client had not seen global_remove yet.
+        bind(&mut client, 2, name, "wl_seat", 1, 3);
+        assert!(!client.is_disconnecting());
+        assert!(!client.has_object(3));
+        assert!(!client.object_versions.contains_key(&3));
+
+        // A name that never existed is still an error.
+        bind(&mut client, 2, name + 100, "wl_seat", 1, 3);
+        assert!(client.is_disconnecting());
+    }
+
+    #[test]
+    fn announces_global_and_global_remove() {
+        use std::io::Read;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let plugged = Arc::new(AtomicBool::new(false));
+        let mut globals = GlobalRegistry::new(GlobalFilter::default());
+        assert_eq!(globals.register(PluggableSeat(Arc::clone(&plugged))), None);
+        let (client, mut peer) = client(globals);
+        let globals = client.globals();
+        let client = Arc::new(Mutex::new(client));
+        send(&mut client.lock().unwrap(), WL_DISPLAY_ID, 1, vec![Argument::NewId(2)]);
+
+        plugged.store(true, Ordering::Relaxed);
+        update_globals(&globals, std::slice::from_ref(&client));
+        plugged.store(false, Ordering::Relaxed);
+        update_globals(&globals, std::slice::from_ref(&client));
+        client.lock().unwrap().flush().unwrap();
+
+        let mut bytes = vec![0; 4096];
+        let length = peer.read(&mut bytes).unwrap();
+        let events = MessageDecoder::new().consume(&bytes[..length]).unwrap();
+        let events: Vec<(u32, u16)> = events.iter().map(|event| (event.object_id, event.opcode)).collect();
+        // wl_registry.global, then wl_registry.global_remove.
+        assert_eq!(events, [(2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn synchronized_subsurfaces_show_their_commit_with_the_parent() {
+        use crate::protocols::wl_subsurface::WlSubsurface;
+
+        const FRAME: u16 = 3;
+        const COMMIT: u16 = 6;
+        const SET_DESYNC: u16 = 5;
+        let (mut client, _peer) = client(GlobalRegistry::new(GlobalFilter::default()));
+        let mut parent = WlSurface::new(10);
+        parent.add_subsurface(11);
+        client.add_object(10, Box::new(parent));
+        client.add_object(11, Box::new(WlSurface::new(11)));
+        client.add_object(12, Box::new(WlSubsurface::new(11, 10)));
+        assert!(client.set_surface_role(11, SurfaceRole::Subsurface, Some(12)));
+        let frame_callbacks = |client: &mut WaylandClient| client.get_object_mut::<WlSurface>(11).unwrap().take_frame_callbacks();
+
+        send(&mut client, 11, FRAME, vec![Argument::NewId(20)]);
+        send(&mut client, 11, COMMIT, vec![]);
+        assert!(frame_callbacks(&mut client).is_empty());
+        send(&mut client, 10, COMMIT, vec![]);
+        assert_eq!(frame_callbacks(&mut client), [20]);
+
+        // Going desynchronized shows what was cached, and later commits apply right away.
+        send(&mut client, 11, FRAME, vec![Argument::NewId(21)]);
+        send(&mut client, 11, COMMIT, vec![]);
+        send(&mut client, 12, SET_DESYNC, vec![]);
+        assert_eq!(frame_callbacks(&mut client), [21]);
+        send(&mut client, 11, FRAME, vec![Argument::NewId(22)]);
+        send(&mut client, 11, COMMIT, vec![]);
+        assert_eq!(frame_callbacks(&mut client), [22]);
+        assert!(!client.is_disconnecting());
     }
 }
@@ -1,15 +1,126 @@
+use std::any::Any;
+use std::fmt;
+
+use crate::message_decoder::DecodeError;
+use crate::protocols::generated::wl_display;
 use crate::wayland_client::WaylandClient;
+use crate::wayland_types::{Argument, Interface, ObjectId};
 
 // Represents a parsed message from the client
 pub struct Request {
     pub object_id: u32,
     pub opcode: u16,
-    pub args: Vec<u32>, // A simplification of argument types
+    /// Arguments decoded according to the interface's request signature.
+    pub args: Vec<Argument>,
+}
+
+/// An event to be sent to the client.
+pub struct Event {
+    pub object_id: u32,
+    pub opcode: u16,
+    pub args: Vec<Argument>,
+}
+
+impl Event {
+    pub fn new(object_id: u32, opcode: u16, args: Vec<Argument>) -> Self {
+        Self { object_id, opcode, args }
+    }
+}
+
+/// A protocol violation by the client. It is reported with wl_display.error,
+/// after which the client is disconnected.
+///
+/// `code` comes from the `error` enum of the interface of `object_id`,
+/// e.g. `generated::xdg_wm_base::error::ROLE`.
+#[derive(Debug)]
+pub struct ProtocolError {
+    pub object_id: ObjectId,
+    pub code: u32,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(object_id: ObjectId, code: u32, message: impl Into<String>) -> Self {
+        Self { object_id, code, message: message.into() }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "object {}, code {}: {}", self.object_id, self.code, self.message)
+    }
 }
 
 /// A trait for any Wayland object that can handle requests.
 /// This is the Rust equivalent of the Wayland_Object base class.
-pub trait WaylandObject {
+/// Objects are `Send` because each client is served from its own task.
+pub trait WaylandObject: Send + 'static {
+    /// The interface this object implements, used to decode its requests.
+    fn interface(&self) -> &'static Interface;
+
     /// Handles an incoming request from a client for this object.
-    fn on_request(&mut self, client: &mut WaylandClient, request: Request);
+    /// A returned error ends the connection.
+    fn on_request(&mut self, client: &mut WaylandClient, request: Request) -> Result<(), ProtocolError>;
+
+    /// Called once the object has been removed from the client, after a
+    /// destructor request or when the client goes away.
+    fn on_destroy(&mut self, client: &mut WaylandClient, object_id: ObjectId);
+
+    // Used by `WaylandClient::get_object` to give typed access to other objects.
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// The request enum generated for an interface by build.rs.
+pub trait ProtocolRequest: Sized {
+    const INTERFACE: &'static Interface;
+
+    /// Converts decoded arguments into the request with the given opcode.
+    fn parse(opcode: u16, args: Vec<Argument>) -> Result<Self, DecodeError>;
+}
+
+/// Implemented by every protocol object. Requests arrive already parsed
+/// into the generated `Request` enum of the object's interface.
+pub trait RequestHandler {
+    type Request: ProtocolRequest;
+
+    fn handle_request(
+        &mut self,
+        client: &mut WaylandClient,
+        object_id: ObjectId,
+        request: Self::Request,
+    ) -> Result<(), ProtocolError>;
+
+    /// Per-type cleanup, see `WaylandObject::on_destroy`.
+    fn on_destroy(&mut self, _client: &mut WaylandClient, _object_id: ObjectId) {}
+}
+
+impl<T: RequestHandler + Send + 'static> WaylandObject for T {
+    fn interface(&self) -> &'static Interface {
+        T::Request::INTERFACE
+    }
+
+    fn on_request(&mut self, client: &mut WaylandClient, request: Request) -> Result<(), ProtocolError> {
+        let interface = self.interface();
+        match T::Request::parse(request.opcode, request.args) {
+            Ok(parsed) => self.handle_request(client, request.object_id, parsed),
+            Err(error) => Err(ProtocolError::new(
+                request.object_id,
+                wl_display::error::INVALID_METHOD,
+                format!("{}@{}.{}: {}", interface.name, request.object_id, request.opcode, error),
+            )),
+        }
+    }
+
+    fn on_destroy(&mut self, client: &mut WaylandClient, object_id: ObjectId) {
+        RequestHandler::on_destroy(self, client, object_id)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
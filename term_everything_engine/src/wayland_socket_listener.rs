@@ -0,0 +1,212 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::net::UnixListener;
+
+use crate::terminal_window::AppState;
+use crate::wayland_client::WaylandClient;
+
+/// Listens on the Wayland socket and serves every client that connects.
+/// This is the Rust equivalent of Wayland_Socket_Listener.ts.
+pub struct WaylandSocketListener {
+    listener: UnixListener,
+    next_client_id: u32,
+}
+
+/// Resolves a socket name to its path in `$XDG_RUNTIME_DIR`.
+pub fn get_socket_path_from_name(socket_name: &str) -> io::Result<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+    Ok(PathBuf::from(runtime_dir).join(socket_name))
+}
+
+/// Display numbers tried when no name is given. wayland-0 and wayland-1 are
+/// usually taken by the host compositor.
+const FIRST_DISPLAY_NUMBER: u32 = 2;
+const LAST_DISPLAY_NUMBER: u32 = 1000;
+
+/// Ownership of a Wayland display name, following the libwayland convention:
+/// whoever holds the flock on `<name>.lock` owns `<name>`, so a socket file
+/// without a locked lock file belongs to a compositor that is gone.
+///
+/// Both files are removed when this is dropped.
+pub struct WaylandDisplay {
+    pub name: String,
+    pub socket_path: PathBuf,
+    lock_path: PathBuf,
+    // The lock is released when the file is closed.
+    _lock_file: File,
+}
+
+impl WaylandDisplay {
+    /// Takes the given display name, or the first free `wayland-N` if there is none.
+    pub fn acquire(name: Option<&str>) -> io::Result<Self> {
+        if let Some(name) = name {
+            return Self::try_acquire(name)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another compositor", name),
+                )
+            });
+        }
+        for i in FIRST_DISPLAY_NUMBER..LAST_DISPLAY_NUMBER {
+            if let Some(display) = Self::try_acquire(&format!("wayland-{}", i))? {
+                return Ok(display);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "Failed to find an open wayland socket name. Pass one in manually with --wayland-display-name <name>",
+        ))
+    }
+
+    /// Returns `None` if another compositor holds the display.
+    fn try_acquire(name: &str) -> io::Result<Option<Self>> {
+        let socket_path = get_socket_path_from_name(name)?;
+        let lock_path = get_socket_path_from_name(&format!("{}.lock", name))?;
+
+        let lock_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o660)
+            .open(&lock_path)?;
+        if unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::WouldBlock {
+                // Held by another compositor, so the lock file is theirs.
+                return Ok(None);
+            }
+            let _ = std::fs::remove_file(&lock_path);
+            return Err(error);
+        }
+
+        // The lock file is ours now, and must not outlive a name we don't take.
+        match remove_stale_socket(&socket_path) {
+            Ok(true) => Ok(Some(Self {
+                name: name.to_string(),
+                socket_path,
+                lock_path,
+                _lock_file: lock_file,
+            })),
+            result => {
+                let _ = std::fs::remove_file(&lock_path);
+                result.map(|_| None)
+            }
+        }
+    }
+}
+
+/// Removes a socket left behind at `socket_path` by a compositor that is gone.
+/// Only called with the lock held. Returns false if something other than a
+/// socket is in the way.
+fn remove_stale_socket(socket_path: &Path) -> io::Result<bool> {
+    match std::fs::symlink_metadata(socket_path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            eprintln!("[WaylandDisplay] Removing stale socket {}", socket_path.display());
+            std::fs::remove_file(socket_path)?;
+            Ok(true)
+        }
+        Ok(_) => {
+            // Not ours to delete; leave it and try the next name.
+            eprintln!("[WaylandDisplay] {} exists and is not a socket", socket_path.display());
+            Ok(false)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+impl Drop for WaylandDisplay {
+    fn drop(&mut self) {
+        // The socket first, so nobody sees an unlocked live socket.
+        let _ = std::fs::remove_file(&self.socket_path);
+        let _ = std::fs::remove_file(&self.lock_path);
+    }
+}
+
+impl WaylandSocketListener {
+    /// Binds the socket of an acquired display. Must be called from within the tokio runtime.
+    pub fn new(display: &WaylandDisplay) -> io::Result<Self> {
+        let listener = UnixListener::bind(&display.socket_path)?;
+        eprintln!("[WaylandSocketListener] Listening on {}", display.socket_path.display());
+        Ok(Self {
+            listener,
+            next_client_id: 1,
+        })
+    }
+
+    /// Accepts clients forever. Each client is served by its own task and
+    /// removed from `AppState.clients` once it disconnects.
+    pub async fn main_loop(mut self, app_state: Arc<Mutex<AppState>>) {
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok((stream, _address)) => stream,
+                Err(e) => {
+                    eprintln!("[WaylandSocketListener] Failed to accept client: {}", e);
+                    return;
+                }
+            };
+            // The client does its own (non-blocking) socket io, so take the fd back from tokio.
+            let client_socket: OwnedFd = match stream.into_std() {
+                Ok(stream) => stream.into(),
+                Err(e) => {
+                    eprintln!("[WaylandSocketListener] Failed to take client socket: {}", e);
+                    continue;
+                }
+            };
+
+            let id = self.next_client_id;
+            self.next_client_id += 1;
+            eprintln!("[WaylandSocketListener] Client {} connected", id);
+
+            let client = {
+                let mut state = app_state.lock().unwrap();
+                let client = Arc::new(Mutex::new(WaylandClient::new(id, client_socket, Arc::clone(&state.globals))));
+                state.clients.push(Arc::clone(&client));
+                client
+            };
+
+            let app_state = Arc::clone(&app_state);
+            tokio::spawn(async move {
+                WaylandClient::main_loop(Arc::clone(&client)).await;
+                client.lock().unwrap().destroy_all_objects();
+                app_state
+                    .lock()
+                    .unwrap()
+                    .clients
+                    .retain(|c| !Arc::ptr_eq(c, &client));
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_no_lock_file_behind_a_name_it_skips() {
+        let runtime_dir = std::env::temp_dir().join(format!("term-everything-test-{}", std::process::id()));
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        // Nothing else in the tests reads XDG_RUNTIME_DIR.
+        std::env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+        std::fs::write(runtime_dir.join("wayland-test"), b"not a socket").unwrap();
+
+        assert!(WaylandDisplay::try_acquire("wayland-test").unwrap().is_none());
+        assert!(!runtime_dir.join("wayland-test.lock").exists());
+        assert!(runtime_dir.join("wayland-test").exists());
+
+        let display = WaylandDisplay::try_acquire("wayland-free").unwrap().unwrap();
+        assert!(runtime_dir.join("wayland-free.lock").exists());
+        drop(display);
+        assert!(!runtime_dir.join("wayland-free.lock").exists());
+
+        std::fs::remove_dir_all(&runtime_dir).unwrap();
+    }
+}
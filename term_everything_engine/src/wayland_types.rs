@@ -0,0 +1,140 @@
+use std::os::unix::io::OwnedFd;
+
+use crate::message_decoder::DecodeError;
+
+pub type ObjectId = u32;
+
+/// Signed 24.8 fixed-point number, as used by the wire format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fixed(pub i32);
+
+/// The type of a single argument in a request or event signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentType {
+    Int,
+    Uint,
+    // No bundled protocol has a fixed or array request argument, but build.rs
+    // emits these for any XML file that adds one.
+    #[allow(dead_code)]
+    Fixed,
+    String,
+    #[allow(dead_code)]
+    Array,
+    Fd,
+    Object,
+    NewId,
+}
+
+/// A decoded argument value.
+/// Nullable strings and objects are represented with `None`.
+#[derive(Debug)]
+pub enum Argument {
+    Int(i32),
+    Uint(u32),
+    Fixed(Fixed),
+    String(Option<String>),
+    Array(Vec<u8>),
+    Fd(OwnedFd),
+    Object(Option<ObjectId>),
+    NewId(ObjectId),
+}
+
+// Conversions used by the generated `Request::parse`. The argument list has
+// already been decoded against the same signature, so a type mismatch only
+// happens if the two get out of sync.
+impl Argument {
+    pub fn into_int(self) -> Result<i32, DecodeError> {
+        match self {
+            Argument::Int(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    pub fn into_uint(self) -> Result<u32, DecodeError> {
+        match self {
+            Argument::Uint(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn into_fixed(self) -> Result<Fixed, DecodeError> {
+        match self {
+            Argument::Fixed(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    pub fn into_string(self) -> Result<String, DecodeError> {
+        self.into_nullable_string()?.ok_or(DecodeError::NullArgument)
+    }
+
+    pub fn into_nullable_string(self) -> Result<Option<String>, DecodeError> {
+        match self {
+            Argument::String(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn into_array(self) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Argument::Array(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    pub fn into_fd(self) -> Result<OwnedFd, DecodeError> {
+        match self {
+            Argument::Fd(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    pub fn into_object(self) -> Result<ObjectId, DecodeError> {
+        self.into_nullable_object()?.ok_or(DecodeError::NullArgument)
+    }
+
+    pub fn into_nullable_object(self) -> Result<Option<ObjectId>, DecodeError> {
+        match self {
+            Argument::Object(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+
+    pub fn into_new_id(self) -> Result<ObjectId, DecodeError> {
+        match self {
+            Argument::NewId(0) => Err(DecodeError::NullArgument),
+            Argument::NewId(value) => Ok(value),
+            _ => Err(DecodeError::TypeMismatch),
+        }
+    }
+}
+
+/// Static description of a Wayland interface, used to decode the
+/// requests sent to objects implementing it.
+pub struct Interface {
+    pub name: &'static str,
+    /// Highest version of the interface this server implements.
+    pub version: u32,
+    /// Argument signature of each request, indexed by opcode.
+    pub requests: &'static [&'static [ArgumentType]],
+    /// The version each request was added in, indexed by opcode.
+    pub request_since: &'static [u32],
+    /// Opcodes of the requests that destroy the object they are sent to.
+    pub destructors: &'static [u16],
+}
+
+impl Interface {
+    pub fn request_signature(&self, opcode: u16) -> Option<&'static [ArgumentType]> {
+        self.requests.get(opcode as usize).copied()
+    }
+
+    pub fn request_since(&self, opcode: u16) -> u32 {
+        self.request_since.get(opcode as usize).copied().unwrap_or(1)
+    }
+
+    pub fn is_destructor(&self, opcode: u16) -> bool {
+        self.destructors.contains(&opcode)
+    }
+}
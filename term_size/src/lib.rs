@@ -0,0 +1,66 @@
+//! The Rust equivalent of TermSize in c_interop, shared by rust_interop and
+//! term_everything_engine.
+
+/// The terminal's size, from stdout, stderr or stdin, whichever is a terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermSize {
+    /// Size of the terminal in cells, or -1 if unknown.
+    pub width_cells: i32,
+    pub height_cells: i32,
+    /// Size of each cell in pixels, or -1 if the terminal does not say.
+    pub width_of_a_cell_in_pixels: i32,
+    pub height_of_a_cell_in_pixels: i32,
+    /// Width over height of a cell.
+    pub font_ratio: f32,
+}
+
+impl TermSize {
+    // Not a Default: it asks the terminal.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut w: libc::winsize = unsafe { std::mem::zeroed() };
+        let found = [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+            .iter()
+            .any(|&fd| unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut w) } >= 0);
+
+        let (mut width_cells, mut height_cells, mut width_pixels, mut height_pixels) = (-1, -1, -1, -1);
+        if found {
+            width_cells = w.ws_col as i32;
+            height_cells = w.ws_row as i32;
+            width_pixels = w.ws_xpixel as i32;
+            height_pixels = w.ws_ypixel as i32;
+        }
+        if width_cells <= 0 {
+            width_cells = -1;
+        }
+        if height_cells <= 2 {
+            height_cells = -1;
+        }
+
+        // Sixel-capable terminals like mlterm fill out the pixel size, most others do not.
+        if width_pixels <= 0 || height_pixels <= 0 {
+            width_pixels = -1;
+            height_pixels = -1;
+        }
+
+        if width_cells > 0 && height_cells > 0 && width_pixels > 0 && height_pixels > 0 {
+            let width_of_a_cell_in_pixels = width_pixels / width_cells;
+            let height_of_a_cell_in_pixels = height_pixels / height_cells;
+            Self {
+                width_cells,
+                height_cells,
+                width_of_a_cell_in_pixels,
+                height_of_a_cell_in_pixels,
+                font_ratio: width_of_a_cell_in_pixels as f32 / height_of_a_cell_in_pixels as f32,
+            }
+        } else {
+            Self {
+                width_cells,
+                height_cells,
+                width_of_a_cell_in_pixels: -1,
+                height_of_a_cell_in_pixels: -1,
+                font_ratio: 0.5,
+            }
+        }
+    }
+}